@@ -0,0 +1,261 @@
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use clap::{Parser, ValueEnum};
+use regex::Regex;
+use serde::Deserialize;
+
+/// Configuration of the controller. Command-line flags take precedence over environment
+/// variables, which take precedence over the configuration file.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Namespaces whose SharedSecrets and SharedSecretRequests are reconciled. Empty means all.
+    pub namespaces: Vec<String>,
+    pub resync_interval: Duration,
+    pub min_resync_interval: Duration,
+    pub max_resync_interval: Duration,
+    /// Maximum deviation from the resync interval, as a fraction of the interval.
+    pub resync_jitter: f64,
+    /// Maximum number of concurrent reconciliations across both controllers. 0 means unlimited.
+    pub concurrency: usize,
+    pub log_format: LogFormat,
+    pub metrics_address: SocketAddr,
+    pub probe_address: SocketAddr,
+    /// Used as field manager, event reporter, `app.kubernetes.io/managed-by` label value and
+    /// name of the leader election lease.
+    pub controller_name: String,
+    pub liveness_window: Duration,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Json,
+    Pretty,
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Shares secrets across namespaces")]
+struct Args {
+    /// YAML file with any of the settings below (in snake_case)
+    #[arg(long, env = "SHAREDSECRETCTL_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Namespaces to reconcile (comma-separated) [default: all namespaces]
+    #[arg(long, env = "SHAREDSECRETCTL_NAMESPACES", value_delimiter = ',')]
+    namespaces: Option<Vec<String>>,
+
+    /// Resync interval of objects that don't set one [default: 300]
+    #[arg(long, env = "SHAREDSECRETCTL_RESYNC_INTERVAL_SECONDS")]
+    resync_interval_seconds: Option<u64>,
+
+    /// Lower bound of the resync interval [default: 10]
+    #[arg(long, env = "SHAREDSECRETCTL_MIN_RESYNC_INTERVAL_SECONDS")]
+    min_resync_interval_seconds: Option<u64>,
+
+    /// Upper bound of the resync interval [default: 86400]
+    #[arg(long, env = "SHAREDSECRETCTL_MAX_RESYNC_INTERVAL_SECONDS")]
+    max_resync_interval_seconds: Option<u64>,
+
+    /// Random deviation from the resync interval, as a fraction of the interval [default: 0.1]
+    #[arg(long, env = "SHAREDSECRETCTL_RESYNC_JITTER")]
+    resync_jitter: Option<f64>,
+
+    /// Maximum number of concurrent reconciliations, 0 for unlimited [default: 0]
+    #[arg(long, env = "SHAREDSECRETCTL_CONCURRENCY")]
+    concurrency: Option<usize>,
+
+    /// Format of the log output [default: json]
+    #[arg(long, env = "SHAREDSECRETCTL_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+
+    /// Listen address of the metrics endpoint [default: 0.0.0.0:8080]
+    #[arg(long, env = "SHAREDSECRETCTL_METRICS_ADDRESS")]
+    metrics_address: Option<SocketAddr>,
+
+    /// Listen address of the probe endpoints, may equal the metrics address [default: 0.0.0.0:8080]
+    #[arg(long, env = "SHAREDSECRETCTL_PROBE_ADDRESS")]
+    probe_address: Option<SocketAddr>,
+
+    /// Name of the controller [default: sharedsecretctl]
+    #[arg(long, env = "SHAREDSECRETCTL_CONTROLLER_NAME")]
+    controller_name: Option<String>,
+
+    /// How long reconciliations may be pending without progress before the liveness probe fails [default: 300]
+    #[arg(long, env = "SHAREDSECRETCTL_LIVENESS_WINDOW_SECONDS")]
+    liveness_window_seconds: Option<u64>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    namespaces: Option<Vec<String>>,
+    resync_interval_seconds: Option<u64>,
+    min_resync_interval_seconds: Option<u64>,
+    max_resync_interval_seconds: Option<u64>,
+    resync_jitter: Option<f64>,
+    concurrency: Option<usize>,
+    log_format: Option<LogFormat>,
+    metrics_address: Option<SocketAddr>,
+    probe_address: Option<SocketAddr>,
+    controller_name: Option<String>,
+    liveness_window_seconds: Option<u64>,
+}
+
+impl Config {
+    /// Reads the configuration from the command line, the environment and the configuration
+    /// file, and validates it.
+    pub fn load() -> anyhow::Result<Config> {
+        let args = Args::parse();
+        let file = match &args.config {
+            Some(path) => read_file(path)?,
+            None => FileConfig::default(),
+        };
+        let default_address = SocketAddr::from(([0, 0, 0, 0], 8080));
+        let config = Config {
+            namespaces: args.namespaces.or(file.namespaces).unwrap_or_default(),
+            resync_interval: Duration::from_secs(args.resync_interval_seconds.or(file.resync_interval_seconds).unwrap_or(5 * 60)),
+            min_resync_interval: Duration::from_secs(args.min_resync_interval_seconds.or(file.min_resync_interval_seconds).unwrap_or(10)),
+            max_resync_interval: Duration::from_secs(args.max_resync_interval_seconds.or(file.max_resync_interval_seconds).unwrap_or(24 * 60 * 60)),
+            resync_jitter: args.resync_jitter.or(file.resync_jitter).unwrap_or(0.1),
+            concurrency: args.concurrency.or(file.concurrency).unwrap_or(0),
+            log_format: args.log_format.or(file.log_format).unwrap_or(LogFormat::Json),
+            metrics_address: args.metrics_address.or(file.metrics_address).unwrap_or(default_address),
+            probe_address: args.probe_address.or(file.probe_address).unwrap_or(default_address),
+            controller_name: args.controller_name.or(file.controller_name).unwrap_or_else(|| "sharedsecretctl".to_string()),
+            liveness_window: Duration::from_secs(args.liveness_window_seconds.or(file.liveness_window_seconds).unwrap_or(5 * 60)),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        // namespaces are DNS labels; the controller name is also used as a label value and a lease name
+        let dns_label = Regex::new("^[a-z0-9]([-a-z0-9]{0,61}[a-z0-9])?$").unwrap();
+        if !dns_label.is_match(&self.controller_name) {
+            bail!("controller_name {:?} must be a lowercase RFC 1123 label (a-z, 0-9 and '-', at most 63 characters)", self.controller_name);
+        }
+        if let Some(namespace) = self.namespaces.iter().find(|ns| !dns_label.is_match(ns)) {
+            bail!("namespaces: {:?} is not a valid namespace name", namespace);
+        }
+        // duplicates are most likely a typo, e.g. a namespace that was meant to be listed instead
+        if let Some((_, namespace)) = self.namespaces.iter().enumerate().find(|(i, ns)| self.namespaces[..*i].contains(ns)) {
+            bail!("namespaces: {:?} is listed more than once", namespace);
+        }
+        if self.min_resync_interval.is_zero() {
+            bail!("min_resync_interval_seconds must be greater than 0");
+        }
+        if self.min_resync_interval > self.max_resync_interval {
+            bail!("min_resync_interval_seconds ({}) must not exceed max_resync_interval_seconds ({})",
+                self.min_resync_interval.as_secs(), self.max_resync_interval.as_secs());
+        }
+        if self.resync_interval < self.min_resync_interval || self.resync_interval > self.max_resync_interval {
+            bail!("resync_interval_seconds ({}) must be between min_resync_interval_seconds ({}) and max_resync_interval_seconds ({})",
+                self.resync_interval.as_secs(), self.min_resync_interval.as_secs(), self.max_resync_interval.as_secs());
+        }
+        if !(0.0..1.0).contains(&self.resync_jitter) {
+            bail!("resync_jitter ({}) must be at least 0 and less than 1", self.resync_jitter);
+        }
+        if self.liveness_window.is_zero() {
+            bail!("liveness_window_seconds must be greater than 0");
+        }
+        Ok(())
+    }
+}
+
+fn read_file(path: &Path) -> anyhow::Result<FileConfig> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read configuration file {}", path.display()))?;
+    serde_yaml::from_str(&content)
+        .with_context(|| format!("Invalid configuration file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        Config {
+            namespaces: vec![],
+            resync_interval: Duration::from_secs(300),
+            min_resync_interval: Duration::from_secs(10),
+            max_resync_interval: Duration::from_secs(86400),
+            resync_jitter: 0.1,
+            concurrency: 0,
+            log_format: LogFormat::Json,
+            metrics_address: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            probe_address: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            controller_name: "sharedsecretctl".to_string(),
+            liveness_window: Duration::from_secs(300),
+        }
+    }
+
+    fn namespaces(namespaces: &[&str]) -> Vec<String> {
+        namespaces.iter().map(|ns| ns.to_string()).collect()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        config().validate().unwrap();
+    }
+
+    #[test]
+    fn controller_name_must_be_a_dns_label() {
+        for name in ["", "Shared", "shared_secret", "-shared", "shared-", &"a".repeat(64)] {
+            let config = Config { controller_name: name.to_string(), ..config() };
+            assert!(config.validate().is_err(), "{:?} should be rejected", name);
+        }
+        let config = Config { controller_name: "a".repeat(63), ..config() };
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn namespaces_must_be_valid_and_distinct() {
+        let valid = Config { namespaces: namespaces(&["team-a", "team-b"]), ..config() };
+        valid.validate().unwrap();
+
+        let invalid = Config { namespaces: namespaces(&["team-a", "Team-B"]), ..config() };
+        assert!(invalid.validate().is_err());
+
+        let duplicate = Config { namespaces: namespaces(&["team-a", "team-b", "team-a"]), ..config() };
+        let error = duplicate.validate().unwrap_err().to_string();
+        assert!(error.contains("\"team-a\" is listed more than once"), "{}", error);
+    }
+
+    #[test]
+    fn resync_intervals_must_be_ordered() {
+        let zero_min = Config { min_resync_interval: Duration::ZERO, ..config() };
+        assert!(zero_min.validate().is_err());
+
+        let min_above_max = Config { min_resync_interval: Duration::from_secs(100), max_resync_interval: Duration::from_secs(50), ..config() };
+        assert!(min_above_max.validate().is_err());
+
+        let below_min = Config { resync_interval: Duration::from_secs(5), ..config() };
+        assert!(below_min.validate().is_err());
+
+        let above_max = Config { resync_interval: Duration::from_secs(100000), ..config() };
+        assert!(above_max.validate().is_err());
+
+        let at_bounds = Config { resync_interval: Duration::from_secs(10), max_resync_interval: Duration::from_secs(10), ..config() };
+        at_bounds.validate().unwrap();
+    }
+
+    #[test]
+    fn resync_jitter_must_be_a_fraction() {
+        for jitter in [0.0, 0.5, 0.99] {
+            Config { resync_jitter: jitter, ..config() }.validate().unwrap();
+        }
+        for jitter in [-0.1, 1.0, 2.0, f64::NAN] {
+            assert!(Config { resync_jitter: jitter, ..config() }.validate().is_err(), "{} should be rejected", jitter);
+        }
+    }
+
+    #[test]
+    fn liveness_window_must_be_positive() {
+        let config = Config { liveness_window: Duration::ZERO, ..config() };
+        assert!(config.validate().is_err());
+    }
+}
@@ -1,35 +1,124 @@
+use std::fmt::Debug;
 use std::sync::Arc;
 
+use futures::TryStreamExt;
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment, StatefulSet};
 use k8s_openapi::api::core::v1::{ObjectReference, Secret};
-use kube::{Api, Client};
-use kube::api::ListParams;
-use kube::runtime::Controller;
+use kube::{Api, Client, Resource, ResourceExt};
+use kube::api::{ListParams, Patch, PatchParams};
+use kube::runtime::controller::Action;
+use kube::runtime::{reflector, watcher, Controller, WatchStreamExt};
 use kube::runtime::events::{Recorder, Reporter};
 use kube::runtime::reflector::{ObjectRef, Store};
 use kube::runtime::watcher::Config;
 use tokio_stream::StreamExt as TokioStreamExt;
-use tracing::error;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::{error, warn};
 
 pub use crd::*;
 
+use crate::{config, Result};
+use crate::controller::backoff::ErrorBackoff;
+use crate::controller::health::Health;
+use crate::controller::index::SharedSecretRequestIndex;
+use crate::controller::leader::{LeaderElector, Leadership};
+use crate::controller::metrics::{Metrics, StoreCollector};
+use crate::controller::resync::ResyncSettings;
+use crate::controller::rollout::{opted_in_workloads, requests_to_roll_out, Workloads};
+use crate::controller::scope::{scoped_apis, scoped_trigger, scoped_watcher};
 use crate::controller::request::{reconcile_shared_secret_request, shared_secret_request_error_policy};
 use crate::controller::shared::{reconcile_shared_secret, shared_secret_error_policy};
 
+mod access;
+mod backoff;
+mod conditions;
 mod crd;
+mod health;
+mod index;
+mod keys;
+mod leader;
+mod metrics;
+mod propagation;
+mod provenance;
 mod shared;
 mod request;
+mod resync;
+mod rollout;
+mod scope;
 
-const CONTROLLER_NAME: &'static str = "sharedsecretctl";
+/// Setting this annotation to a new value (e.g. the current time) forces a reconciliation. The
+/// handled value is acknowledged in the status.
+const RECONCILE_REQUESTED_AT_ANNOTATION: &str = "sharedsecretctl.klauser.link/reconcile-requested-at";
 
-pub struct State {
+fn reconcile_requested_at<K: ResourceExt>(obj: &K) -> Option<&str> {
+    obj.annotations().get(RECONCILE_REQUESTED_AT_ANNOTATION).map(|s| &s[..])
+}
 
+/// State shared between the controllers and the HTTP server of the binary.
+#[derive(Clone)]
+pub struct State {
+    metrics: Metrics,
+    health: Health,
+    resync: ResyncSettings,
+    controller_name: String,
+    namespaces: Vec<String>,
+    reconcile_permits: Option<Arc<Semaphore>>,
 }
 
 impl State {
-    fn to_context(&self, client: Client, reporter: Arc<Reporter>) -> Arc<Context> {
+    pub fn new(config: &config::Config) -> Self {
+        State {
+            metrics: Metrics::default(),
+            health: Health::new(config.liveness_window),
+            resync: ResyncSettings {
+                default_interval: config.resync_interval,
+                min_interval: config.min_resync_interval,
+                max_interval: config.max_resync_interval,
+                jitter: config.resync_jitter,
+            },
+            controller_name: config.controller_name.clone(),
+            namespaces: config.namespaces.clone(),
+            reconcile_permits: Some(config.concurrency)
+                .filter(|concurrency| *concurrency > 0)
+                .map(|concurrency| Arc::new(Semaphore::new(concurrency))),
+        }
+    }
+
+    /// Whether the CRDs are installed and the caches of both controllers are populated.
+    pub fn is_ready(&self) -> bool {
+        self.health.is_ready()
+    }
+
+    /// Whether the reconcile loop is making progress.
+    pub fn is_alive(&self) -> bool {
+        self.health.is_alive()
+    }
+
+    /// All metrics in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        prometheus::TextEncoder::new()
+            .encode_to_string(&self.metrics.registry.gather())
+            .unwrap_or_else(|e| format!("# failed to encode metrics: {}\n", e))
+    }
+
+    fn to_context(&self, client: Client, reporter: Arc<Reporter>, request_store: Store<SharedSecretRequest>, request_index: SharedSecretRequestIndex, workloads: Workloads, leadership: Leadership) -> Arc<Context> {
         Arc::new(Context {
             client,
             reporter,
+            request_store,
+            request_index,
+            workloads,
+            resync: self.resync.clone(),
+            controller_name: self.controller_name.clone(),
+            namespaces: self.namespaces.clone(),
+            reconcile_permits: self.reconcile_permits.clone(),
+            shared_secret_backoff: ErrorBackoff::default(),
+            request_backoff: ErrorBackoff::default(),
+            metrics: self.metrics.clone(),
+            health: self.health.clone(),
+            leadership,
         })
     }
 }
@@ -38,76 +127,214 @@ impl State {
 pub(in crate::controller) struct Context {
     pub client: Client,
     pub reporter: Arc<Reporter>,
+    pub request_store: Store<SharedSecretRequest>,
+    pub request_index: SharedSecretRequestIndex,
+    pub workloads: Workloads,
+    pub resync: ResyncSettings,
+    pub controller_name: String,
+    /// Namespaces whose objects are reconciled. Empty means all.
+    pub namespaces: Vec<String>,
+    pub reconcile_permits: Option<Arc<Semaphore>>,
+    pub shared_secret_backoff: ErrorBackoff<SharedSecret>,
+    pub request_backoff: ErrorBackoff<SharedSecretRequest>,
+    pub metrics: Metrics,
+    pub health: Health,
+    pub leadership: Leadership,
 }
 
 impl Context {
     pub fn event_recorder(&self, reference: ObjectReference) -> Recorder {
         Recorder::new(self.client.clone(), (*self.reporter).clone(), reference)
     }
+
+    pub fn watches_namespace(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|ns| ns == namespace)
+    }
+
+    /// Waits until the configured reconcile concurrency permits another reconciliation.
+    pub async fn reconcile_permit(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.reconcile_permits {
+            Some(permits) => Some(permits.acquire().await.expect("reconcile semaphore to never be closed")),
+            None => None,
+        }
+    }
 }
 
 pub async fn run(state: State) {
     let client = Client::try_default().await.expect("Failed to create kube client");
-    let shared_secrets = Api::<SharedSecret>::all(client.clone());
-    let secrets = Api::<Secret>::all(client.clone());
-    let shared_secret_requests = Api::<SharedSecretRequest>::all(client.clone());
     let reporter = Arc::new(Reporter {
-        controller: CONTROLLER_NAME.into(),
+        controller: state.controller_name.clone(),
         instance: std::env::var("CONTROLLER_POD_NAME").ok(),
     });
 
     // Verify that we can access the CRD. If we can't, this usually means that
     // the CRD is not installed. (Could also be a permissions issue.)
-    if let Err(e) = shared_secrets.list(&ListParams::default().limit(1)).await {
-        error!("CRD SharedSecret is not queryable; {e:?}. Is the CRD installed?");
-        std::process::exit(1);
+    for shared_secrets in scoped_apis::<SharedSecret>(&client, &state.namespaces) {
+        if let Err(e) = shared_secrets.list(&ListParams::default().limit(1)).await {
+            error!("CRD SharedSecret is not queryable; {e:?}. Is the CRD installed?");
+            std::process::exit(1);
+        }
     }
-    if let Err(e) = shared_secret_requests.list(&ListParams::default().limit(1)).await {
-        error!("CRD SharedSecretRequest is not queryable; {e:?}. Is the CRD installed?");
-        std::process::exit(1);
+    for shared_secret_requests in scoped_apis::<SharedSecretRequest>(&client, &state.namespaces) {
+        if let Err(e) = shared_secret_requests.list(&ListParams::default().limit(1)).await {
+            error!("CRD SharedSecretRequest is not queryable; {e:?}. Is the CRD installed?");
+            std::process::exit(1);
+        }
     }
+    state.health.mark_crds_verified();
+
+    // Only one replica reconciles at a time. The others keep their caches warm so that they can
+    // take over right away.
+    let identity = reporter.instance.clone()
+        .unwrap_or_else(|| format!("{}-{:08x}", state.controller_name, rand::random::<u32>()));
+    let (elector, leadership) = LeaderElector::new(client.clone(), &state.controller_name, identity);
 
-    let context = state.to_context(client.clone(), reporter.clone());
-    let shared_controller = Controller::new(shared_secrets.clone(), Config::default().any_semantic());
-    let shared_store = shared_controller.store();
+    let (request_store, request_writer) = reflector::store();
+    let request_index = SharedSecretRequestIndex::default();
+    let (deployments, deployment_writer) = reflector::store();
+    let (stateful_sets, stateful_set_writer) = reflector::store();
+    let (daemon_sets, daemon_set_writer) = reflector::store();
+    let workloads = Workloads { deployments, stateful_sets, daemon_sets };
+    let context = state.to_context(client.clone(), reporter.clone(), request_store.clone(), request_index.clone(), workloads, leadership.clone());
+    let (shared_store, shared_writer) = reflector::store();
+    let shared_secret_stream = reflector(shared_writer, scoped_watcher(&client, &state.namespaces, Config::default().any_semantic()))
+        .inspect_ok({
+            let backoff = context.shared_secret_backoff.clone();
+            let health = state.health.clone();
+            move |event| {
+                backoff.apply_watcher_event(event);
+                if let watcher::Event::Restarted(_) = event {
+                    health.mark_shared_secrets_synced();
+                }
+            }
+        })
+        .applied_objects();
+    let shared_controller = Controller::for_stream(shared_secret_stream, shared_store.clone());
+    if let Err(e) = state.metrics.registry.register(Box::new(StoreCollector::new(request_store.clone(), shared_store.clone()))) {
+        error!("Failed to register store metrics: {e:?}");
+    }
     let shared_secret_controller = shared_controller
         .shutdown_on_signal()
+        .reconcile_all_on(leadership.acquired())
+        .watches_stream(scoped_trigger(&client, &state.namespaces, Config::default().any_semantic()), |request: SharedSecretRequest| {
+            // only deleted requests can unblock the deletion of a SharedSecret
+            request.metadata.deletion_timestamp.as_ref().map(|_| {
+                ObjectRef::new(&request.spec.shared_secret.name).within(&request.spec.shared_secret.namespace)
+            })
+        })
+        .watches_stream(scoped_trigger(&client, &state.namespaces, Config::default().any_semantic()), {
+            let shared_store = shared_store.clone();
+            move |secret: Secret| {
+                shared_secrets_for_secret(&shared_store, &secret).into_iter()
+                    .map(|shared_secret| ObjectRef::from_obj(&*shared_secret))
+                    .collect::<Vec<_>>()
+            }
+        })
         .run(reconcile_shared_secret, shared_secret_error_policy, context.clone())
         .map(|_| ());
 
     let shared_secret_request_controller = futures::StreamExt::boxed({
-        let request_controller = Controller::new(
-            shared_secret_requests,
-            Config::default().any_semantic(),
-        );
-        let request_store = request_controller.store();
-        let request_controller = request_controller
+        let requests = reflector(request_writer, scoped_watcher(&client, &state.namespaces, Config::default().any_semantic()))
+            .inspect_ok({
+                let request_index = request_index.clone();
+                let backoff = context.request_backoff.clone();
+                let health = state.health.clone();
+                move |event| {
+                    request_index.apply_watcher_event(event);
+                    backoff.apply_watcher_event(event);
+                    if let watcher::Event::Restarted(_) = event {
+                        health.mark_requests_synced();
+                    }
+                }
+            })
+            .applied_objects();
+        let request_controller = Controller::for_stream(requests, request_store)
             .shutdown_on_signal()
-            .watches(shared_secrets, Config::default().any_semantic(), move |shared_secret| {
-                matching_requests(&request_store, &shared_secret)
+            .reconcile_all_on(leadership.acquired())
+            .watches_stream(scoped_trigger(&client, &state.namespaces, Config::default().any_semantic()), {
+                let request_index = request_index.clone();
+                move |shared_secret: SharedSecret| matching_requests(&request_index, &shared_secret)
             });
-        let request_store = request_controller.store();
         request_controller
-            .watches(secrets.clone(), Config::default().any_semantic(), move |secret| {
-                shared_store.find(|shared| {
-                    Some(&shared.spec.secret_name[..]) == secret.metadata.name.as_ref().map(|s| &s[..])
-                }).and_then(|shared_secret| matching_requests(&request_store, &*shared_secret))
+            .watches_stream(scoped_trigger(&client, &state.namespaces, Config::default().any_semantic()), move |secret: Secret| {
+                shared_secrets_for_secret(&shared_store, &secret).into_iter()
+                    .flat_map(|shared_secret| matching_requests(&request_index, &shared_secret))
+                    .collect::<Vec<_>>()
+            })
+            .owns_stream(scoped_trigger::<Secret>(&client, &state.namespaces, Config::default().any_semantic()))
+            .watches_stream(opted_in_workloads(&client, &state.namespaces, deployment_writer), {
+                let request_store = context.request_store.clone();
+                move |workload: Deployment| requests_to_roll_out(&request_store, &workload)
+            })
+            .watches_stream(opted_in_workloads(&client, &state.namespaces, stateful_set_writer), {
+                let request_store = context.request_store.clone();
+                move |workload: StatefulSet| requests_to_roll_out(&request_store, &workload)
+            })
+            .watches_stream(opted_in_workloads(&client, &state.namespaces, daemon_set_writer), {
+                let request_store = context.request_store.clone();
+                move |workload: DaemonSet| requests_to_roll_out(&request_store, &workload)
             })
-            .owns(secrets, Config::default().any_semantic())
             .run(reconcile_shared_secret_request, shared_secret_request_error_policy, context.clone())
     }.map(|_| ()));
 
-    futures::StreamExt::for_each(
+    let controllers = futures::StreamExt::for_each(
         shared_secret_controller.merge(shared_secret_request_controller),
-        |_| futures::future::ready(()))
-        .await;
+        |_| futures::future::ready(()));
+    tokio::select! {
+        _ = controllers => {},
+        _ = elector.run() => {},
+    }
+    // hand over to a standby right away instead of letting it wait for the lease to expire
+    elector.release().await;
+}
+
+/// Bookkeeping after the finalizer of a reconciliation ran: resets the error backoff of `obj_ref`
+/// on success, counts failures, and records the error in `last_error` of the status if it changed.
+async fn record_reconcile_result<K>(
+    ctx: &Context,
+    api: &Api<K>,
+    backoff: &ErrorBackoff<K>,
+    controller: &str,
+    obj_ref: &ObjectRef<K>,
+    previous_error: Option<String>,
+    result: &Result<Action>,
+) where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    // surface permanent errors in the status, since they won't go away by retrying
+    let last_error = match result {
+        Ok(_) => {
+            backoff.reset(obj_ref);
+            None
+        }
+        Err(e) if e.is_permanent() => Some(e.to_string()),
+        Err(_) => previous_error.clone(),
+    };
+    if let Err(e) = result {
+        ctx.metrics.reconcile_failed(controller, e);
+    }
+    if last_error != previous_error {
+        let patch = Patch::Merge(json!({ "status": { "last_error": last_error } }));
+        if let Err(e) = api.patch_status(&obj_ref.name, &PatchParams::apply(&ctx.controller_name), &patch).await {
+            warn!("Failed to record the last error of {} \"{}\" in {}: {:?}",
+                K::kind(&()), obj_ref.name, obj_ref.namespace.as_deref().unwrap_or_default(), e);
+        }
+    }
 }
 
-fn matching_requests(request_store: &Store<SharedSecretRequest>, shared_secret: &SharedSecret) -> Option<ObjectRef<SharedSecretRequest>> {
-    request_store.find(|request| {
-        Some(&request.spec.shared_secret.name[..]) == shared_secret.metadata.name.as_ref().map(|s| &s[..])
-            && Some(&request.spec.shared_secret.namespace[..]) == shared_secret.metadata.namespace.as_ref().map(|s| &s[..])
-    })
-        .map(|request| ObjectRef::from_obj(&*request))
+/// All SharedSecrets in the namespace of `secret` that share it.
+fn shared_secrets_for_secret(shared_store: &Store<SharedSecret>, secret: &Secret) -> Vec<Arc<SharedSecret>> {
+    shared_store.state().into_iter()
+        .filter(|shared| {
+            shared.metadata.namespace == secret.metadata.namespace
+                && Some(&shared.spec.secret_name) == secret.metadata.name.as_ref()
+        })
+        .collect()
 }
 
+fn matching_requests(request_index: &SharedSecretRequestIndex, shared_secret: &SharedSecret) -> Vec<ObjectRef<SharedSecretRequest>> {
+    match (&shared_secret.metadata.namespace, &shared_secret.metadata.name) {
+        (Some(namespace), Some(name)) => request_index.find(namespace, name),
+        _ => vec![],
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+
+use crate::controller::{SharedSecret, SharedSecretAccessPolicy, SharedSecretRequest, SharedSecretRequestReference, SharedSecretRequestState};
+
+/// Outcome of checking whether a SharedSecretRequest may read a SharedSecret.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(in crate::controller) enum AccessDecision {
+    /// Access is permitted, possibly only until `expires_at` if it stems from a time-bounded grant.
+    Permitted { expires_at: Option<DateTime<Utc>> },
+    /// The namespace of the request is not covered by the access policy.
+    Forbidden,
+    /// The namespace of the request was only covered by time-bounded grants that have expired.
+    Expired,
+    PendingApproval,
+    Denied,
+}
+
+impl AccessDecision {
+    /// The request state to report if access is not permitted.
+    pub fn denied_state(self) -> Option<SharedSecretRequestState> {
+        match self {
+            AccessDecision::Permitted { .. } => None,
+            AccessDecision::Forbidden => Some(SharedSecretRequestState::Forbidden),
+            AccessDecision::Expired => Some(SharedSecretRequestState::Expired),
+            AccessDecision::PendingApproval => Some(SharedSecretRequestState::PendingApproval),
+            AccessDecision::Denied => Some(SharedSecretRequestState::Denied),
+        }
+    }
+}
+
+impl SharedSecret {
+    /// Applies the approval workflow to a request whose namespace is permitted by the access policy.
+    pub(in crate::controller) fn approval_decision(&self, request: &SharedSecretRequest, expires_at: Option<DateTime<Utc>>) -> AccessDecision {
+        let reference = SharedSecretRequestReference {
+            namespace: request.metadata.namespace.clone().unwrap_or_default(),
+            name: request.metadata.name.clone().unwrap_or_default(),
+        };
+        if self.spec.denied_requests.contains(&reference) {
+            AccessDecision::Denied
+        } else if !self.spec.requires_approval || self.spec.approved_requests.contains(&reference) {
+            AccessDecision::Permitted { expires_at }
+        } else {
+            AccessDecision::PendingApproval
+        }
+    }
+}
+
+impl SharedSecretAccessPolicy {
+    /// Whether the policy mentions label selectors, in which case the labels of the requesting
+    /// namespace need to be looked up before calling [`SharedSecretAccessPolicy::evaluate`].
+    pub fn requires_namespace_labels(&self) -> bool {
+        !self.namespace_selectors.is_empty()
+    }
+
+    /// Decides whether `namespace` may read the shared secret at time `now`. Permanent rules take
+    /// precedence over time-bounded grants; of several active grants, the one expiring last wins.
+    pub(in crate::controller) fn evaluate(&self, namespace: &str, namespace_labels: &BTreeMap<String, String>, now: DateTime<Utc>) -> AccessDecision {
+        let permanent = self.namespaces.iter().any(|n| n == namespace)
+            || self.namespace_patterns.iter().any(|p| glob_matches(p, namespace))
+            || self.namespace_selectors.iter().any(|s| selector_matches(s, namespace_labels));
+        if permanent {
+            return AccessDecision::Permitted { expires_at: None };
+        }
+
+        let mut grants = self.grants.iter().filter(|g| g.namespace == namespace).peekable();
+        if grants.peek().is_none() {
+            return AccessDecision::Forbidden;
+        }
+        match grants.map(|g| g.not_after).filter(|not_after| *not_after > now).max() {
+            Some(not_after) => AccessDecision::Permitted { expires_at: Some(not_after) },
+            None => AccessDecision::Expired,
+        }
+    }
+}
+
+/// Matches `text` against a glob `pattern` supporting `*` (any sequence) and `?` (any character).
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` in the pattern and the text position it was tried at
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Evaluates a label selector with the usual Kubernetes semantics: all `match_labels` and all
+/// `match_expressions` must be satisfied. An empty selector matches everything.
+fn selector_matches(selector: &LabelSelector, labels: &BTreeMap<String, String>) -> bool {
+    let labels_match = selector.match_labels.iter()
+        .flatten()
+        .all(|(key, value)| labels.get(key) == Some(value));
+    let expressions_match = selector.match_expressions.iter()
+        .flatten()
+        .all(|requirement| {
+            let values = requirement.values.as_deref().unwrap_or_default();
+            let label = labels.get(&requirement.key);
+            match &requirement.operator[..] {
+                "In" => label.map(|l| values.contains(l)).unwrap_or(false),
+                "NotIn" => label.map(|l| !values.contains(l)).unwrap_or(true),
+                "Exists" => label.is_some(),
+                "DoesNotExist" => label.is_none(),
+                // unknown operators never match; this mirrors the API server rejecting them
+                _ => false,
+            }
+        });
+    labels_match && expressions_match
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelectorRequirement;
+
+    use crate::controller::SharedSecretAccessGrant;
+
+    use super::*;
+
+    fn labels(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn requirement(key: &str, operator: &str, values: &[&str]) -> LabelSelector {
+        LabelSelector {
+            match_expressions: Some(vec![LabelSelectorRequirement {
+                key: key.to_string(),
+                operator: operator.to_string(),
+                values: Some(values.iter().map(|v| v.to_string()).collect()).filter(|v: &Vec<_>| !v.is_empty()),
+            }]),
+            ..Default::default()
+        }
+    }
+
+    fn grant(namespace: &str, not_after: DateTime<Utc>) -> SharedSecretAccessGrant {
+        SharedSecretAccessGrant { namespace: namespace.to_string(), not_after }
+    }
+
+    #[test]
+    fn glob_matches_stars() {
+        assert!(glob_matches("*-prod", "team-a-prod"));
+        assert!(!glob_matches("*-prod", "team-a-staging"));
+        assert!(glob_matches("team-*", "team-a"));
+        assert!(glob_matches("team-*", "team-"));
+        assert!(!glob_matches("team-*", "other-a"));
+        assert!(glob_matches("*-*-prod", "team-a-prod"));
+        assert!(glob_matches("t*a*d", "team-a-prod"));
+        assert!(!glob_matches("t*a*x", "team-a-prod"));
+        assert!(glob_matches("**", "anything"));
+    }
+
+    #[test]
+    fn glob_matches_question_mark() {
+        assert!(glob_matches("team-?", "team-a"));
+        assert!(!glob_matches("team-?", "team-"));
+        assert!(!glob_matches("team-?", "team-ab"));
+        assert!(glob_matches("?*", "a"));
+    }
+
+    #[test]
+    fn glob_matches_empty() {
+        assert!(glob_matches("", ""));
+        assert!(!glob_matches("", "team-a"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("?", ""));
+        assert!(!glob_matches("team", ""));
+    }
+
+    #[test]
+    fn selector_matches_in_and_not_in() {
+        let selector = requirement("env", "In", &["prod", "staging"]);
+        assert!(selector_matches(&selector, &labels(&[("env", "prod")])));
+        assert!(!selector_matches(&selector, &labels(&[("env", "dev")])));
+        assert!(!selector_matches(&selector, &labels(&[])));
+
+        let selector = requirement("env", "NotIn", &["prod"]);
+        assert!(selector_matches(&selector, &labels(&[("env", "dev")])));
+        assert!(!selector_matches(&selector, &labels(&[("env", "prod")])));
+        assert!(selector_matches(&selector, &labels(&[])));
+    }
+
+    #[test]
+    fn selector_matches_exists_and_does_not_exist() {
+        let selector = requirement("env", "Exists", &[]);
+        assert!(selector_matches(&selector, &labels(&[("env", "")])));
+        assert!(!selector_matches(&selector, &labels(&[("team", "a")])));
+
+        let selector = requirement("env", "DoesNotExist", &[]);
+        assert!(selector_matches(&selector, &labels(&[("team", "a")])));
+        assert!(!selector_matches(&selector, &labels(&[("env", "prod")])));
+    }
+
+    #[test]
+    fn selector_matches_labels_and_expressions() {
+        let mut selector = requirement("env", "In", &["prod"]);
+        selector.match_labels = Some(labels(&[("team", "a")]));
+        assert!(selector_matches(&selector, &labels(&[("team", "a"), ("env", "prod")])));
+        assert!(!selector_matches(&selector, &labels(&[("team", "b"), ("env", "prod")])));
+        assert!(!selector_matches(&selector, &labels(&[("team", "a")])));
+    }
+
+    #[test]
+    fn empty_selector_matches_everything() {
+        let selector = LabelSelector::default();
+        assert!(selector_matches(&selector, &labels(&[])));
+        assert!(selector_matches(&selector, &labels(&[("env", "prod")])));
+    }
+
+    #[test]
+    fn evaluate_permanent_rule_wins_over_expired_grant() {
+        let now = Utc::now();
+        let policy = SharedSecretAccessPolicy {
+            namespace_patterns: vec!["team-*".to_string()],
+            grants: vec![grant("team-a", now - Duration::hours(1))],
+            ..Default::default()
+        };
+        assert_eq!(policy.evaluate("team-a", &labels(&[]), now), AccessDecision::Permitted { expires_at: None });
+    }
+
+    #[test]
+    fn evaluate_expired_grant() {
+        let now = Utc::now();
+        let policy = SharedSecretAccessPolicy {
+            grants: vec![grant("team-a", now - Duration::hours(1))],
+            ..Default::default()
+        };
+        assert_eq!(policy.evaluate("team-a", &labels(&[]), now), AccessDecision::Expired);
+        assert_eq!(policy.evaluate("team-b", &labels(&[]), now), AccessDecision::Forbidden);
+    }
+
+    #[test]
+    fn evaluate_latest_active_grant_wins() {
+        let now = Utc::now();
+        let latest = now + Duration::hours(2);
+        let policy = SharedSecretAccessPolicy {
+            grants: vec![
+                grant("team-a", now + Duration::hours(1)),
+                grant("team-a", latest),
+                grant("team-a", now - Duration::hours(1)),
+                grant("team-b", now + Duration::hours(3)),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(policy.evaluate("team-a", &labels(&[]), now), AccessDecision::Permitted { expires_at: Some(latest) });
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use kube::Resource;
+use kube::runtime::reflector::ObjectRef;
+use kube::runtime::watcher;
+use rand::Rng;
+
+use crate::Error;
+
+/// Delay after the first failure of a reconciliation.
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound of the delay, also used right away for permanent errors.
+const MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Per-object exponential backoff for failed reconciliations. The number of consecutive failures
+/// is tracked per object and forgotten once a reconciliation succeeds or the object is deleted.
+pub(in crate::controller) struct ErrorBackoff<K: Resource> {
+    failures: Arc<Mutex<HashMap<ObjectRef<K>, u32>>>,
+}
+
+impl<K: Resource> Default for ErrorBackoff<K> {
+    fn default() -> Self {
+        ErrorBackoff { failures: Default::default() }
+    }
+}
+
+impl<K: Resource> Clone for ErrorBackoff<K> {
+    fn clone(&self) -> Self {
+        ErrorBackoff { failures: self.failures.clone() }
+    }
+}
+
+impl<K> ErrorBackoff<K>
+where
+    K: Resource,
+    K::DynamicType: Default + Eq + Hash + Clone,
+{
+    /// Records a failed reconciliation of `obj` and returns how long to wait before retrying.
+    pub fn next_delay(&self, obj: &K, error: &Error) -> Duration {
+        let failures = {
+            let mut failures = self.failures.lock().expect("backoff lock to not be poisoned");
+            let count = failures.entry(ObjectRef::from_obj(obj)).or_insert(0);
+            *count = count.saturating_add(1);
+            *count
+        };
+        let delay = if error.is_permanent() {
+            MAX_DELAY
+        } else {
+            INITIAL_DELAY.saturating_mul(2u32.saturating_pow(failures - 1)).min(MAX_DELAY)
+        };
+        // "equal jitter": keep at least half of the delay so that the backoff still grows
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// Forgets the failures of `obj` after a successful reconciliation.
+    pub fn reset(&self, obj: &ObjectRef<K>) {
+        self.failures.lock().expect("backoff lock to not be poisoned").remove(obj);
+    }
+
+    /// Forgets the failures of objects that are gone, as they are never reconciled successfully
+    /// again. Fed with the watcher events of the reconciled objects.
+    pub fn apply_watcher_event(&self, event: &watcher::Event<K>) {
+        let mut failures = self.failures.lock().expect("backoff lock to not be poisoned");
+        match event {
+            watcher::Event::Applied(_) => {}
+            watcher::Event::Deleted(obj) => {
+                failures.remove(&ObjectRef::from_obj(obj));
+            }
+            watcher::Event::Restarted(objs) => {
+                let listed: HashSet<ObjectRef<K>> = objs.iter().map(ObjectRef::from_obj).collect();
+                failures.retain(|obj, _| listed.contains(obj));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::{SharedSecret, SharedSecretSpec};
+
+    use super::*;
+
+    fn shared_secret(name: &str) -> SharedSecret {
+        let mut shared_secret = SharedSecret::new(name, SharedSecretSpec {
+            secret_name: name.to_string(),
+            access_policy: Default::default(),
+            requires_approval: false,
+            approved_requests: vec![],
+            denied_requests: vec![],
+            export: Default::default(),
+            suspend: false,
+            resync_interval_seconds: None,
+        });
+        shared_secret.metadata.namespace = Some("a".to_string());
+        shared_secret
+    }
+
+    fn tracked(backoff: &ErrorBackoff<SharedSecret>) -> Vec<String> {
+        let mut names: Vec<String> = backoff.failures.lock().unwrap().keys().map(|obj| obj.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn delay_grows_with_consecutive_failures() {
+        let backoff = ErrorBackoff::default();
+        let error = Error::DeletionBlocked("dependents".to_string());
+        let delays: Vec<Duration> = (0..4).map(|_| backoff.next_delay(&shared_secret("x"), &error)).collect();
+        for (failures, delay) in delays.iter().enumerate() {
+            let max = INITIAL_DELAY * 2u32.pow(failures as u32);
+            assert!(*delay >= max / 2 && *delay <= max, "{:?} after {} failures", delay, failures + 1);
+        }
+
+        backoff.reset(&ObjectRef::from_obj(&shared_secret("x")));
+        assert!(backoff.next_delay(&shared_secret("x"), &error) <= INITIAL_DELAY);
+    }
+
+    #[test]
+    fn permanent_errors_wait_for_the_maximum_delay() {
+        let backoff = ErrorBackoff::default();
+        let delay = backoff.next_delay(&shared_secret("x"), &Error::InvalidSpec("invalid".to_string()));
+        assert!(delay >= MAX_DELAY / 2 && delay <= MAX_DELAY, "{:?}", delay);
+    }
+
+    #[test]
+    fn deleted_objects_are_forgotten() {
+        let backoff = ErrorBackoff::default();
+        let error = Error::DeletionBlocked("dependents".to_string());
+        for name in ["x", "y", "z"] {
+            backoff.next_delay(&shared_secret(name), &error);
+        }
+
+        backoff.apply_watcher_event(&watcher::Event::Applied(shared_secret("x")));
+        assert_eq!(tracked(&backoff), ["x", "y", "z"]);
+        backoff.apply_watcher_event(&watcher::Event::Deleted(shared_secret("x")));
+        assert_eq!(tracked(&backoff), ["y", "z"]);
+        backoff.apply_watcher_event(&watcher::Event::Restarted(vec![shared_secret("z")]));
+        assert_eq!(tracked(&backoff), ["z"]);
+    }
+}
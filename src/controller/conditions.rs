@@ -0,0 +1,116 @@
+use chrono::Utc;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+
+use crate::controller::{SharedSecretRequestState, SharedSecretState};
+
+const READY: &str = "Ready";
+const SOURCE_AVAILABLE: &str = "SourceAvailable";
+const AUTHORIZED: &str = "Authorized";
+const SYNCED: &str = "Synced";
+
+/// A condition as derived from the state machine, before it is merged with the conditions that
+/// are already present on the object.
+pub(in crate::controller) struct DesiredCondition {
+    type_: &'static str,
+    /// `None` means that the status of the condition is unknown.
+    status: Option<bool>,
+    reason: String,
+    message: String,
+}
+
+impl DesiredCondition {
+    fn new(type_: &'static str, status: Option<bool>, reason: impl Into<String>, message: impl Into<String>) -> Self {
+        DesiredCondition { type_, status, reason: reason.into(), message: message.into() }
+    }
+
+    fn status_str(&self) -> &'static str {
+        match self.status {
+            Some(true) => "True",
+            Some(false) => "False",
+            None => "Unknown",
+        }
+    }
+}
+
+/// Builds the new list of conditions. The `lastTransitionTime` of a condition is only bumped if
+/// its status changes.
+pub(in crate::controller) fn merge_conditions(previous: &[Condition], desired: Vec<DesiredCondition>, generation: Option<i64>) -> Vec<Condition> {
+    desired.into_iter()
+        .map(|desired| {
+            let status = desired.status_str();
+            let last_transition_time = previous.iter()
+                .find(|c| c.type_ == desired.type_ && c.status == status)
+                .map(|c| c.last_transition_time.clone())
+                .unwrap_or_else(|| Time(Utc::now()));
+            Condition {
+                type_: desired.type_.to_string(),
+                status: status.to_string(),
+                reason: desired.reason,
+                message: desired.message,
+                observed_generation: generation,
+                last_transition_time,
+            }
+        })
+        .collect()
+}
+
+/// Whether two lists of conditions differ in anything but their timestamps.
+pub(in crate::controller) fn conditions_differ(a: &[Condition], b: &[Condition]) -> bool {
+    a.len() != b.len() || a.iter().zip(b).any(|(a, b)| {
+        a.type_ != b.type_ || a.status != b.status || a.reason != b.reason || a.message != b.message
+    })
+}
+
+impl SharedSecretState {
+    pub(in crate::controller) fn desired_conditions(self) -> Vec<DesiredCondition> {
+        let reason = format!("{:?}", self);
+        let (ready, source_available, message) = match self {
+            SharedSecretState::Uninitialized => (None, None, "The SharedSecret has not been validated yet"),
+            SharedSecretState::SecretMissing => (Some(false), Some(false), "The source secret does not exist"),
+            SharedSecretState::SecretInvalid => (Some(false), Some(false), "The source secret has no data"),
+            SharedSecretState::Valid => (Some(true), Some(true), "The source secret can be shared"),
+            SharedSecretState::Suspended => (None, None, "Reconciliation of the SharedSecret is suspended"),
+            SharedSecretState::DeletionBlocked => (Some(false), None, "Deletion is blocked by SharedSecretRequests that still reference the SharedSecret"),
+        };
+        vec![
+            DesiredCondition::new(READY, ready, reason.clone(), message),
+            DesiredCondition::new(SOURCE_AVAILABLE, source_available, reason, message),
+        ]
+    }
+}
+
+impl SharedSecretRequestState {
+    pub(in crate::controller) fn desired_conditions(self) -> Vec<DesiredCondition> {
+        use SharedSecretRequestState::*;
+
+        let reason = format!("{:?}", self);
+        // status of the SourceAvailable, Authorized and Synced conditions, in that order
+        let (source_available, authorized, synced, message) = match self {
+            Uninitialized => (None, None, None, "The SharedSecretRequest has not been reconciled yet"),
+            SharedSecretMissing => (Some(false), None, Some(false), "The referenced SharedSecret does not exist"),
+            SharedSecretInvalid => (Some(false), None, Some(false), "The referenced SharedSecret is not valid"),
+            Forbidden => (Some(true), Some(false), Some(false), "The namespace is not permitted by the access policy of the SharedSecret"),
+            PendingApproval => (Some(true), Some(false), Some(false), "The request awaits approval by the owner of the SharedSecret"),
+            Denied => (Some(true), Some(false), Some(false), "The owner of the SharedSecret denied the request"),
+            Expired => (Some(true), Some(false), Some(false), "The grant for this namespace has expired"),
+            Revoked => (Some(true), Some(false), Some(false), "Access to the SharedSecret was revoked and the local copy removed"),
+            RequiredKeyMissing => (Some(true), Some(true), Some(false), "A key required by the key mapping is missing from the shared secret"),
+            TypeMismatch => (Some(true), Some(true), Some(false), "The local secret exists with a different type and is not managed by the SharedSecretRequest"),
+            Conflict => (Some(true), Some(true), Some(false), "The local secret is claimed by another SharedSecretRequest or was not created by this one"),
+            InvalidSpec => (None, None, Some(false), "The spec of the SharedSecretRequest is invalid"),
+            Suspended => (Some(true), Some(true), None, "Synchronization is suspended; the local secret keeps its current data"),
+            Synchronized => (Some(true), Some(true), Some(true), "The local secret is in sync with the SharedSecret"),
+        };
+        let ready = match (source_available, authorized, synced) {
+            (Some(true), Some(true), Some(true)) => Some(true),
+            (None, None, None) => None,
+            _ => Some(false),
+        };
+        vec![
+            DesiredCondition::new(READY, ready, reason.clone(), message),
+            DesiredCondition::new(SOURCE_AVAILABLE, source_available, reason.clone(), message),
+            DesiredCondition::new(AUTHORIZED, authorized, reason.clone(), message),
+            DesiredCondition::new(SYNCED, synced, reason, message),
+        ]
+    }
+}
@@ -1,4 +1,5 @@
 use chrono::{DateTime, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, LabelSelector};
 use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -8,11 +9,84 @@ use serde::{Deserialize, Serialize};
 #[kube(status = "SharedSecretStatus")]
 pub struct SharedSecretSpec {
     pub secret_name: String,
+    #[serde(default)]
+    pub access_policy: SharedSecretAccessPolicy,
+    /// When set, requests from permitted namespaces are only served once they are listed in
+    /// `approved_requests`.
+    #[serde(default)]
+    pub requires_approval: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub approved_requests: Vec<SharedSecretRequestReference>,
+    /// Requests that must never be served. Takes precedence over `approved_requests`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_requests: Vec<SharedSecretRequestReference>,
+    #[serde(default)]
+    pub export: KeyExport,
+    /// Stops validating the SharedSecret. Requests stop synchronizing but keep their local copies.
+    #[serde(default)]
+    pub suspend: bool,
+    /// Interval in which the source secret is validated even if no change was observed. Bounded
+    /// by the controller; the controller default applies if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resync_interval_seconds: Option<u64>,
+}
+
+/// Restricts the keys of the source secret that leave its namespace. Requests only ever see the
+/// exported keys.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, JsonSchema)]
+pub struct KeyExport {
+    /// Keys to export. If empty, all keys are exported.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Keys to never export.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+/// Namespaces that may request a SharedSecret. A namespace is permitted if it matches any of the
+/// entries. The namespace of the SharedSecret itself is always permitted.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, JsonSchema)]
+pub struct SharedSecretAccessPolicy {
+    /// Exact namespace names.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<String>,
+    /// Glob patterns for namespace names (`*` matches any sequence, `?` any single character).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespace_patterns: Vec<String>,
+    /// Label selectors evaluated against the labels of the requesting namespace.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespace_selectors: Vec<LabelSelector>,
+    /// Time-bounded grants for individual namespaces. Local copies are removed once a grant expires.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub grants: Vec<SharedSecretAccessGrant>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct SharedSecretAccessGrant {
+    pub namespace: String,
+    pub not_after: DateTime<Utc>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
 pub struct SharedSecretStatus {
     pub state: SharedSecretState,
+    #[serde(rename = "observedGeneration", default, skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    /// Value of the reconcile-requested-at annotation that was handled last.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_handled_reconcile_at: Option<String>,
+    /// The last error that retrying won't fix on its own. Cleared by the next successful
+    /// reconciliation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Names of the keys that requests can copy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exported_keys: Vec<String>,
+    /// SharedSecretRequests that still reference the SharedSecret while its deletion is blocked.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependents: Vec<SharedSecretRequestReference>,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
@@ -22,6 +96,8 @@ pub enum SharedSecretState {
     SecretMissing,
     SecretInvalid,
     Valid,
+    Suspended,
+    DeletionBlocked,
 }
 
 #[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
@@ -30,6 +106,94 @@ pub enum SharedSecretState {
 pub struct SharedSecretRequestSpec {
     pub shared_secret: SharedSecretReference,
     pub local_secret_name: Option<String>,
+    #[serde(default)]
+    pub deletion_policy: DeletionPolicy,
+    #[serde(default)]
+    pub keys: KeyMapping,
+    #[serde(default)]
+    pub metadata_propagation: MetadataPropagation,
+    #[serde(default)]
+    pub adoption: AdoptionPolicy,
+    /// Freezes the local secret on its current data. Revocation of access is still enforced.
+    #[serde(default)]
+    pub suspend: bool,
+    /// Interval in which the local secret is checked even if no change was observed. Bounded by
+    /// the controller; the controller default applies if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resync_interval_seconds: Option<u64>,
+}
+
+/// Whether a local secret that already exists, but was not created by the SharedSecretRequest,
+/// may be taken over.
+#[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, Eq, PartialEq, JsonSchema)]
+pub enum AdoptionPolicy {
+    /// Never touch a pre-existing local secret.
+    #[default]
+    Never,
+    /// Adopt a pre-existing local secret only if it contains no data.
+    IfEmpty,
+    /// Always adopt a pre-existing local secret, overwriting its data.
+    Always,
+}
+
+/// Labels and annotations of the source secret to copy to the local secret. Nothing is copied by
+/// default.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, JsonSchema)]
+pub struct MetadataPropagation {
+    #[serde(default)]
+    pub labels: PrefixFilter,
+    #[serde(default)]
+    pub annotations: PrefixFilter,
+}
+
+/// Selects keys that start with any of the `allow` prefixes and none of the `deny` prefixes. Use
+/// an empty prefix to allow everything.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, JsonSchema)]
+pub struct PrefixFilter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+/// Selects and renames the keys copied from the shared secret. Keys are first selected, then
+/// renamed; the prefix is added to all selected keys that are not renamed explicitly.
+#[derive(Deserialize, Serialize, Clone, Default, Debug, JsonSchema)]
+pub struct KeyMapping {
+    /// Keys to copy. Keys listed here must be present in the shared secret.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Regular expression selecting additional keys to copy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_regex: Option<String>,
+    /// Keys to never copy, even if they are selected otherwise.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Keys to copy under a different name. Keys listed here must be present in the shared secret.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rename: Vec<KeyRename>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub struct KeyRename {
+    pub from: String,
+    pub to: String,
+}
+
+/// What happens to the local secret when the SharedSecretRequest is deleted.
+#[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, Eq, PartialEq, JsonSchema)]
+pub enum DeletionPolicy {
+    /// Delete the local secret if it was created by the SharedSecretRequest.
+    #[default]
+    Delete,
+    /// Keep the local secret and its data, but remove the owner reference to the
+    /// SharedSecretRequest and all labels and annotations that mark it as managed.
+    Orphan,
+    /// Keep the local secret, its data and its provenance labels and annotations. Only the
+    /// owner reference is removed, so that garbage collection does not delete the secret.
+    Retain,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
@@ -38,10 +202,34 @@ pub struct SharedSecretReference {
     pub name: String,
 }
 
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq, JsonSchema)]
+pub struct SharedSecretRequestReference {
+    pub namespace: String,
+    pub name: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Default, Debug, JsonSchema)]
 pub struct SharedSecretRequestStatus {
     pub state: SharedSecretRequestState,
     pub last_updated_at: Option<DateTime<Utc>>,
+    /// When the local secret was last confirmed to be in sync with the SharedSecret. Moves on
+    /// every successful sync, even if nothing had to be changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_synced_at: Option<DateTime<Utc>>,
+    /// Value of the reconcile-requested-at annotation that was handled last.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_handled_reconcile_at: Option<String>,
+    /// The last error that retrying won't fix on its own. Cleared by the next successful
+    /// reconciliation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Name of the local secret that is currently managed by the SharedSecretRequest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_local_secret: Option<String>,
+    #[serde(rename = "observedGeneration", default, skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, Default, Debug, Eq, PartialEq, JsonSchema)]
@@ -50,5 +238,15 @@ pub enum SharedSecretRequestState {
     Uninitialized,
     SharedSecretMissing,
     SharedSecretInvalid,
+    Forbidden,
+    PendingApproval,
+    Denied,
+    Expired,
+    Revoked,
+    RequiredKeyMissing,
+    TypeMismatch,
+    Conflict,
+    InvalidSpec,
+    Suspended,
     Synchronized,
 }
@@ -0,0 +1,91 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Readiness and liveness of the controllers, as reported by the probe endpoints.
+#[derive(Clone)]
+pub(in crate::controller) struct Health {
+    inner: Arc<HealthInner>,
+}
+
+struct HealthInner {
+    crds_verified: AtomicBool,
+    requests_synced: AtomicBool,
+    shared_secrets_synced: AtomicBool,
+    reconciles_in_flight: AtomicUsize,
+    last_progress: Mutex<Instant>,
+    liveness_window: Duration,
+}
+
+impl Health {
+    pub fn new(liveness_window: Duration) -> Self {
+        Health {
+            inner: Arc::new(HealthInner {
+                crds_verified: AtomicBool::new(false),
+                requests_synced: AtomicBool::new(false),
+                shared_secrets_synced: AtomicBool::new(false),
+                reconciles_in_flight: AtomicUsize::new(0),
+                last_progress: Mutex::new(Instant::now()),
+                liveness_window,
+            }),
+        }
+    }
+
+    pub fn mark_crds_verified(&self) {
+        self.inner.crds_verified.store(true, Ordering::Relaxed);
+    }
+
+    pub fn mark_requests_synced(&self) {
+        self.inner.requests_synced.store(true, Ordering::Relaxed);
+    }
+
+    pub fn mark_shared_secrets_synced(&self) {
+        self.inner.shared_secrets_synced.store(true, Ordering::Relaxed);
+    }
+
+    /// Ready once the CRDs were found and both reflector stores received their initial listing.
+    pub fn is_ready(&self) -> bool {
+        self.inner.crds_verified.load(Ordering::Relaxed)
+            && self.inner.requests_synced.load(Ordering::Relaxed)
+            && self.inner.shared_secrets_synced.load(Ordering::Relaxed)
+    }
+
+    /// Alive unless reconciliations are pending and none of them completed within the liveness
+    /// window. An idle controller is alive.
+    pub fn is_alive(&self) -> bool {
+        if self.inner.reconciles_in_flight.load(Ordering::Relaxed) == 0 {
+            return true;
+        }
+        self.last_progress().elapsed() <= self.inner.liveness_window
+    }
+
+    /// Tracks a reconciliation until the returned guard is dropped.
+    pub fn reconcile_started(&self) -> ReconcileProgress {
+        if self.inner.reconciles_in_flight.fetch_add(1, Ordering::Relaxed) == 0 {
+            // the window starts with the first pending reconciliation, not with the last one of
+            // the previous busy period
+            self.record_progress();
+        }
+        ReconcileProgress { health: self.clone() }
+    }
+
+    fn last_progress(&self) -> Instant {
+        *self.inner.last_progress.lock().expect("health lock to not be poisoned")
+    }
+
+    fn record_progress(&self) {
+        *self.inner.last_progress.lock().expect("health lock to not be poisoned") = Instant::now();
+    }
+}
+
+/// Records progress of the reconcile loop when dropped, i.e. when a reconciliation completes.
+pub(in crate::controller) struct ReconcileProgress {
+    health: Health,
+}
+
+impl Drop for ReconcileProgress {
+    fn drop(&mut self) {
+        self.health.record_progress();
+        self.health.inner.reconciles_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
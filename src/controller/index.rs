@@ -0,0 +1,148 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use kube::runtime::reflector::ObjectRef;
+use kube::runtime::watcher;
+
+use crate::controller::SharedSecretRequest;
+
+/// (namespace, name) of a SharedSecret
+type SharedSecretKey = (String, String);
+
+/// Index of SharedSecretRequests by the SharedSecret they reference. Kept up to date from the
+/// watcher events of the SharedSecretRequest reflector so that changes to a SharedSecret can be
+/// fanned out to all of its consumers without scanning the whole store.
+#[derive(Clone, Default)]
+pub(in crate::controller) struct SharedSecretRequestIndex {
+    inner: Arc<RwLock<IndexInner>>,
+}
+
+#[derive(Default)]
+struct IndexInner {
+    by_shared_secret: HashMap<SharedSecretKey, HashSet<ObjectRef<SharedSecretRequest>>>,
+    by_request: HashMap<ObjectRef<SharedSecretRequest>, SharedSecretKey>,
+}
+
+impl SharedSecretRequestIndex {
+    pub fn apply_watcher_event(&self, event: &watcher::Event<SharedSecretRequest>) {
+        let mut inner = self.inner.write().expect("SharedSecretRequest index lock to not be poisoned");
+        match event {
+            watcher::Event::Applied(request) => inner.insert(request),
+            watcher::Event::Deleted(request) => inner.remove(&ObjectRef::from_obj(request)),
+            watcher::Event::Restarted(requests) => {
+                *inner = IndexInner::default();
+                for request in requests {
+                    inner.insert(request);
+                }
+            }
+        }
+    }
+
+    /// All SharedSecretRequests that reference the SharedSecret `name` in `namespace`.
+    pub fn find(&self, namespace: &str, name: &str) -> Vec<ObjectRef<SharedSecretRequest>> {
+        let inner = self.inner.read().expect("SharedSecretRequest index lock to not be poisoned");
+        inner.by_shared_secret.get(&(namespace.to_string(), name.to_string()))
+            .map(|requests| requests.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl IndexInner {
+    fn insert(&mut self, request: &SharedSecretRequest) {
+        let request_ref = ObjectRef::from_obj(request);
+        self.remove(&request_ref);
+        let key = (request.spec.shared_secret.namespace.clone(), request.spec.shared_secret.name.clone());
+        self.by_shared_secret.entry(key.clone()).or_default().insert(request_ref.clone());
+        self.by_request.insert(request_ref, key);
+    }
+
+    fn remove(&mut self, request_ref: &ObjectRef<SharedSecretRequest>) {
+        let Some(key) = self.by_request.remove(request_ref) else {
+            return;
+        };
+        if let Some(requests) = self.by_shared_secret.get_mut(&key) {
+            requests.remove(request_ref);
+            if requests.is_empty() {
+                self.by_shared_secret.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn request(namespace: &str, name: &str, shared_secret: (&str, &str)) -> SharedSecretRequest {
+        serde_json::from_value(json!({
+            "apiVersion": "sharedsecretctl.klauser.link/v1",
+            "kind": "SharedSecretRequest",
+            "metadata": { "namespace": namespace, "name": name },
+            "spec": { "shared_secret": { "namespace": shared_secret.0, "name": shared_secret.1 } },
+        })).unwrap()
+    }
+
+    /// "namespace/name" of the requests referencing the SharedSecret, sorted.
+    fn found(index: &SharedSecretRequestIndex, namespace: &str, name: &str) -> Vec<String> {
+        let mut requests: Vec<String> = index.find(namespace, name).into_iter()
+            .map(|request| format!("{}/{}", request.namespace.unwrap(), request.name))
+            .collect();
+        requests.sort();
+        requests
+    }
+
+    #[test]
+    fn finds_requests_by_shared_secret() {
+        let index = SharedSecretRequestIndex::default();
+        index.apply_watcher_event(&watcher::Event::Applied(request("b", "r1", ("a", "s"))));
+        index.apply_watcher_event(&watcher::Event::Applied(request("c", "r2", ("a", "s"))));
+        index.apply_watcher_event(&watcher::Event::Applied(request("c", "r3", ("a", "other"))));
+
+        assert_eq!(found(&index, "a", "s"), ["b/r1", "c/r2"]);
+        assert_eq!(found(&index, "a", "other"), ["c/r3"]);
+        // the namespace is part of the key
+        assert!(found(&index, "b", "s").is_empty());
+    }
+
+    #[test]
+    fn changed_reference_is_rekeyed() {
+        let index = SharedSecretRequestIndex::default();
+        index.apply_watcher_event(&watcher::Event::Applied(request("b", "r1", ("a", "s"))));
+        index.apply_watcher_event(&watcher::Event::Applied(request("b", "r1", ("a", "t"))));
+
+        assert!(found(&index, "a", "s").is_empty());
+        assert_eq!(found(&index, "a", "t"), ["b/r1"]);
+        // empty entries don't linger
+        assert!(!index.inner.read().unwrap().by_shared_secret.contains_key(&("a".to_string(), "s".to_string())));
+    }
+
+    #[test]
+    fn deleted_requests_are_removed() {
+        let index = SharedSecretRequestIndex::default();
+        index.apply_watcher_event(&watcher::Event::Applied(request("b", "r1", ("a", "s"))));
+        index.apply_watcher_event(&watcher::Event::Applied(request("c", "r2", ("a", "s"))));
+        index.apply_watcher_event(&watcher::Event::Deleted(request("b", "r1", ("a", "s"))));
+        assert_eq!(found(&index, "a", "s"), ["c/r2"]);
+
+        // deleting an unknown request is a no-op
+        index.apply_watcher_event(&watcher::Event::Deleted(request("d", "r4", ("a", "s"))));
+        assert_eq!(found(&index, "a", "s"), ["c/r2"]);
+    }
+
+    #[test]
+    fn restart_rebuilds_the_index() {
+        let index = SharedSecretRequestIndex::default();
+        index.apply_watcher_event(&watcher::Event::Applied(request("b", "r1", ("a", "s"))));
+        index.apply_watcher_event(&watcher::Event::Applied(request("c", "r2", ("a", "t"))));
+        index.apply_watcher_event(&watcher::Event::Restarted(vec![
+            request("c", "r2", ("a", "s")),
+            request("d", "r3", ("a", "s")),
+        ]));
+
+        assert_eq!(found(&index, "a", "s"), ["c/r2", "d/r3"]);
+        assert!(found(&index, "a", "t").is_empty());
+        assert_eq!(index.inner.read().unwrap().by_request.len(), 2);
+    }
+}
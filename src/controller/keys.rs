@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::ByteString;
+use regex::Regex;
+use thiserror::Error;
+
+use crate::controller::{KeyExport, KeyMapping};
+
+#[derive(Error, Debug)]
+pub(in crate::controller) enum KeyMappingError {
+    #[error("include_regex is not a valid regular expression: {0}")]
+    InvalidRegex(#[source] regex::Error),
+
+    #[error("several keys are mapped to {0:?}")]
+    DuplicateTarget(String),
+
+    #[error("required keys are missing from the shared secret: {}", .0.join(", "))]
+    MissingKeys(Vec<String>),
+}
+
+impl KeyMapping {
+    /// Keys that must be present in the shared secret, because they are named explicitly.
+    fn required_keys(&self) -> impl Iterator<Item = &String> {
+        self.include.iter().chain(self.rename.iter().map(|r| &r.from))
+    }
+
+    fn selects_everything(&self) -> bool {
+        self.include.is_empty() && self.include_regex.is_none()
+    }
+
+    /// Transforms the data of the shared secret into the data of the local secret.
+    pub(in crate::controller) fn apply(&self, data: &BTreeMap<String, ByteString>) -> Result<BTreeMap<String, ByteString>, KeyMappingError> {
+        let include_regex = self.include_regex.as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(KeyMappingError::InvalidRegex)?;
+
+        let missing: BTreeSet<String> = self.required_keys()
+            .filter(|key| !data.contains_key(*key))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            return Err(KeyMappingError::MissingKeys(missing.into_iter().collect()));
+        }
+
+        let mut mapped = BTreeMap::new();
+        for (key, value) in data {
+            let selected = self.selects_everything()
+                || self.include.contains(key)
+                || include_regex.as_ref().map(|r| r.is_match(key)).unwrap_or(false);
+            if self.exclude.contains(key) {
+                continue;
+            }
+
+            let renames: Vec<&String> = self.rename.iter().filter(|r| &r.from == key).map(|r| &r.to).collect();
+            let targets = if !renames.is_empty() {
+                renames.into_iter().cloned().collect()
+            } else if selected {
+                vec![format!("{}{}", self.prefix.as_deref().unwrap_or_default(), key)]
+            } else {
+                vec![]
+            };
+            for target in targets {
+                if mapped.insert(target.clone(), value.clone()).is_some() {
+                    return Err(KeyMappingError::DuplicateTarget(target));
+                }
+            }
+        }
+        Ok(mapped)
+    }
+}
+
+impl KeyExport {
+    /// The subset of the source secret's data that may leave its namespace.
+    pub fn apply(&self, data: &BTreeMap<String, ByteString>) -> BTreeMap<String, ByteString> {
+        data.iter()
+            .filter(|(key, _)| self.include.is_empty() || self.include.contains(key))
+            .filter(|(key, _)| !self.exclude.contains(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// Describes which keys were added, changed and removed between `old` and `new`, without values.
+pub(in crate::controller) fn describe_changes(old: &BTreeMap<String, ByteString>, new: &BTreeMap<String, ByteString>) -> String {
+    let added: Vec<&str> = new.keys().filter(|key| !old.contains_key(*key)).map(|k| &k[..]).collect();
+    let changed: Vec<&str> = new.iter()
+        .filter(|(key, value)| old.get(*key).map(|old_value| old_value != *value).unwrap_or(false))
+        .map(|(k, _)| &k[..])
+        .collect();
+    let removed: Vec<&str> = old.keys().filter(|key| !new.contains_key(*key)).map(|k| &k[..]).collect();
+    let list = |keys: Vec<&str>| if keys.is_empty() { "-".to_string() } else { keys.join(", ") };
+    format!("added: {}; changed: {}; removed: {}", list(added), list(changed), list(removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::controller::KeyRename;
+
+    use super::*;
+
+    fn data(keys: &[&str]) -> BTreeMap<String, ByteString> {
+        keys.iter().map(|key| (key.to_string(), ByteString(key.as_bytes().to_vec()))).collect()
+    }
+
+    fn keys(data: &BTreeMap<String, ByteString>) -> Vec<&str> {
+        data.keys().map(|k| &k[..]).collect()
+    }
+
+    fn rename(from: &str, to: &str) -> KeyRename {
+        KeyRename { from: from.to_string(), to: to.to_string() }
+    }
+
+    #[test]
+    fn empty_mapping_copies_everything() {
+        let mapped = KeyMapping::default().apply(&data(&["a", "b"])).unwrap();
+        assert_eq!(mapped, data(&["a", "b"]));
+    }
+
+    #[test]
+    fn include_and_include_regex_select_keys() {
+        let mapping = KeyMapping {
+            include: vec!["a".to_string()],
+            include_regex: Some("^tls\\.".to_string()),
+            ..Default::default()
+        };
+        let mapped = mapping.apply(&data(&["a", "b", "tls.crt", "tls.key"])).unwrap();
+        assert_eq!(keys(&mapped), ["a", "tls.crt", "tls.key"]);
+    }
+
+    #[test]
+    fn renames_bypass_include_but_not_exclude() {
+        let mapping = KeyMapping {
+            include: vec!["a".to_string()],
+            exclude: vec!["c".to_string()],
+            rename: vec![rename("b", "renamed-b"), rename("c", "renamed-c")],
+            ..Default::default()
+        };
+        let mapped = mapping.apply(&data(&["a", "b", "c"])).unwrap();
+        assert_eq!(keys(&mapped), ["a", "renamed-b"]);
+        assert_eq!(mapped["renamed-b"], ByteString(b"b".to_vec()));
+    }
+
+    #[test]
+    fn prefix_is_skipped_for_renamed_keys() {
+        let mapping = KeyMapping {
+            rename: vec![rename("b", "renamed-b")],
+            prefix: Some("app-".to_string()),
+            ..Default::default()
+        };
+        let mapped = mapping.apply(&data(&["a", "b"])).unwrap();
+        assert_eq!(keys(&mapped), ["app-a", "renamed-b"]);
+    }
+
+    #[test]
+    fn key_may_be_renamed_to_several_targets() {
+        let mapping = KeyMapping {
+            rename: vec![rename("a", "x"), rename("a", "y")],
+            ..Default::default()
+        };
+        let mapped = mapping.apply(&data(&["a"])).unwrap();
+        assert_eq!(keys(&mapped), ["x", "y"]);
+    }
+
+    #[test]
+    fn duplicate_target_is_an_error() {
+        let mapping = KeyMapping {
+            rename: vec![rename("b", "a")],
+            ..Default::default()
+        };
+        match mapping.apply(&data(&["a", "b"])) {
+            Err(KeyMappingError::DuplicateTarget(target)) => assert_eq!(target, "a"),
+            result => panic!("expected DuplicateTarget, got {:?}", result),
+        }
+
+        let mapping = KeyMapping {
+            rename: vec![rename("a", "x"), rename("b", "x")],
+            ..Default::default()
+        };
+        assert!(matches!(mapping.apply(&data(&["a", "b"])), Err(KeyMappingError::DuplicateTarget(target)) if target == "x"));
+    }
+
+    #[test]
+    fn missing_included_and_renamed_keys_are_an_error() {
+        let mapping = KeyMapping {
+            include: vec!["a".to_string(), "missing-include".to_string()],
+            rename: vec![rename("missing-rename", "x")],
+            ..Default::default()
+        };
+        match mapping.apply(&data(&["a"])) {
+            Err(KeyMappingError::MissingKeys(missing)) => assert_eq!(missing, ["missing-include", "missing-rename"]),
+            result => panic!("expected MissingKeys, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        let mapping = KeyMapping {
+            include: vec!["missing".to_string()],
+            include_regex: Some("(".to_string()),
+            ..Default::default()
+        };
+        // the regex is checked before the keys
+        assert!(matches!(mapping.apply(&data(&["a"])), Err(KeyMappingError::InvalidRegex(_))));
+    }
+
+    #[test]
+    fn export_everything_by_default() {
+        assert_eq!(KeyExport::default().apply(&data(&["a", "b"])), data(&["a", "b"]));
+    }
+
+    #[test]
+    fn export_include_and_exclude() {
+        let export = KeyExport {
+            include: vec!["a".to_string(), "b".to_string(), "missing".to_string()],
+            exclude: vec!["b".to_string()],
+        };
+        assert_eq!(keys(&export.apply(&data(&["a", "b", "c"]))), ["a"]);
+
+        let export = KeyExport {
+            exclude: vec!["b".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(keys(&export.apply(&data(&["a", "b", "c"]))), ["a", "c"]);
+    }
+}
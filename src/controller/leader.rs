@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::Stream;
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta};
+use kube::{Api, Client};
+use kube::api::PostParams;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::Result;
+
+/// How long a lease is valid after it was last renewed.
+const LEASE_DURATION: Duration = Duration::from_secs(15);
+/// A leader steps down if it could not renew the lease for this long, so that it stops before
+/// another replica may take over.
+const RENEW_DEADLINE: Duration = Duration::from_secs(10);
+/// Interval of renewals, and of acquisition attempts by standbys.
+const RETRY_PERIOD: Duration = Duration::from_secs(2);
+
+/// Whether this replica currently holds the lease. Cheap to clone.
+#[derive(Clone)]
+pub(in crate::controller) struct Leadership {
+    is_leader: watch::Receiver<bool>,
+}
+
+impl Leadership {
+    pub fn is_leader(&self) -> bool {
+        *self.is_leader.borrow()
+    }
+
+    /// Yields whenever this replica becomes the leader.
+    pub fn acquired(&self) -> impl Stream<Item = ()> + Send + Sync + 'static {
+        futures::stream::unfold(self.is_leader.clone(), |mut is_leader| async move {
+            loop {
+                is_leader.changed().await.ok()?;
+                if *is_leader.borrow() {
+                    return Some(((), is_leader));
+                }
+            }
+        })
+    }
+}
+
+/// Competes for a `coordination.k8s.io/v1` Lease with the other replicas of the controller.
+pub(in crate::controller) struct LeaderElector {
+    leases: Api<Lease>,
+    lease_name: String,
+    identity: String,
+    is_leader: watch::Sender<bool>,
+}
+
+impl LeaderElector {
+    /// Creates an elector for the lease `lease_name` in the default namespace of `client` (the
+    /// namespace of the pod, when running in-cluster).
+    pub fn new(client: Client, lease_name: &str, identity: String) -> (Self, Leadership) {
+        let (is_leader, leadership) = watch::channel(false);
+        let elector = LeaderElector {
+            leases: Api::default_namespaced(client),
+            lease_name: lease_name.to_string(),
+            identity,
+            is_leader,
+        };
+        (elector, Leadership { is_leader: leadership })
+    }
+
+    /// Tries to acquire or renew the lease every retry period, forever.
+    pub async fn run(&self) {
+        let mut last_renewal = None;
+        loop {
+            match self.try_acquire_or_renew().await {
+                Ok(true) => {
+                    last_renewal = Some(tokio::time::Instant::now());
+                    self.set_leader(true);
+                }
+                Ok(false) => {
+                    last_renewal = None;
+                    self.set_leader(false);
+                }
+                Err(e) => {
+                    warn!("Failed to acquire or renew lease \"{}\": {:?}", self.lease_name, e);
+                    if last_renewal.map(|t| t.elapsed() > RENEW_DEADLINE).unwrap_or(true) {
+                        self.set_leader(false);
+                    }
+                }
+            }
+            tokio::time::sleep(RETRY_PERIOD).await;
+        }
+    }
+
+    /// Gives up the lease, if held, so that a standby can take over without waiting for it to
+    /// expire.
+    pub async fn release(&self) {
+        if !*self.is_leader.borrow() {
+            return;
+        }
+        self.set_leader(false);
+        let result = async {
+            let Some(mut lease) = self.leases.get_opt(&self.lease_name).await? else {
+                return Ok(());
+            };
+            let spec = lease.spec.get_or_insert_with(Default::default);
+            if spec.holder_identity.as_deref() != Some(&self.identity[..]) {
+                return Ok(());
+            }
+            spec.holder_identity = None;
+            spec.acquire_time = None;
+            spec.renew_time = None;
+            self.leases.replace(&self.lease_name, &PostParams::default(), &lease).await?;
+            Ok::<_, kube::Error>(())
+        }.await;
+        match result {
+            Ok(()) => info!("Released lease \"{}\"", self.lease_name),
+            Err(e) => warn!("Failed to release lease \"{}\": {:?}", self.lease_name, e),
+        }
+    }
+
+    fn set_leader(&self, is_leader: bool) {
+        self.is_leader.send_if_modified(|current| {
+            if *current == is_leader {
+                return false;
+            }
+            if is_leader {
+                info!("Acquired lease \"{}\" as {}", self.lease_name, self.identity);
+            } else {
+                info!("Lost lease \"{}\"; no longer reconciling", self.lease_name);
+            }
+            *current = is_leader;
+            true
+        });
+    }
+
+    /// Returns whether this replica holds the lease afterwards.
+    async fn try_acquire_or_renew(&self) -> Result<bool> {
+        let now = Utc::now();
+        let Some(mut lease) = self.leases.get_opt(&self.lease_name).await? else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.lease_name.clone()),
+                    ..ObjectMeta::default()
+                },
+                spec: Some(LeaseSpec {
+                    holder_identity: Some(self.identity.clone()),
+                    lease_duration_seconds: Some(LEASE_DURATION.as_secs() as i32),
+                    acquire_time: Some(MicroTime(now)),
+                    renew_time: Some(MicroTime(now)),
+                    lease_transitions: Some(0),
+                }),
+            };
+            return self.write(self.leases.create(&PostParams::default(), &lease).await);
+        };
+
+        let spec = lease.spec.get_or_insert_with(Default::default);
+        let held_by_us = spec.holder_identity.as_deref() == Some(&self.identity[..]);
+        if !held_by_us {
+            let lease_duration = chrono::Duration::seconds(spec.lease_duration_seconds.unwrap_or(LEASE_DURATION.as_secs() as i32).into());
+            let expired = spec.holder_identity.is_none()
+                || spec.renew_time.as_ref().map(|t| t.0 + lease_duration < now).unwrap_or(true);
+            if !expired {
+                debug!("Lease \"{}\" is held by {:?}", self.lease_name, spec.holder_identity);
+                return Ok(false);
+            }
+            spec.holder_identity = Some(self.identity.clone());
+            spec.acquire_time = Some(MicroTime(now));
+            spec.lease_transitions = Some(spec.lease_transitions.unwrap_or_default() + 1);
+        }
+        spec.lease_duration_seconds = Some(LEASE_DURATION.as_secs() as i32);
+        spec.renew_time = Some(MicroTime(now));
+        // the resourceVersion of the lease makes this fail if another replica got there first
+        self.write(self.leases.replace(&self.lease_name, &PostParams::default(), &lease).await)
+    }
+
+    fn write(&self, result: kube::Result<Lease>) -> Result<bool> {
+        match result {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(response)) if response.code == 409 => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+use kube::ResourceExt;
+use kube::runtime::reflector::Store;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
+
+use crate::controller::{SharedSecret, SharedSecretRequest};
+use crate::Error;
+
+const NAMESPACE: &str = "sharedsecretctl";
+
+/// Metrics about the reconciliations of both controllers.
+#[derive(Clone)]
+pub(in crate::controller) struct Metrics {
+    pub registry: Registry,
+    reconciliations: IntCounterVec,
+    failures: IntCounterVec,
+    reconcile_duration: HistogramVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new();
+        let reconciliations = IntCounterVec::new(
+            Opts::new("reconciliations_total", "Number of reconciliations").namespace(NAMESPACE),
+            &["controller"],
+        ).unwrap();
+        let failures = IntCounterVec::new(
+            Opts::new("reconciliation_errors_total", "Number of failed reconciliations by error").namespace(NAMESPACE),
+            &["controller", "error"],
+        ).unwrap();
+        let reconcile_duration = HistogramVec::new(
+            HistogramOpts::new("reconcile_duration_seconds", "Duration of reconciliations")
+                .namespace(NAMESPACE)
+                .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.]),
+            &["controller"],
+        ).unwrap();
+        registry.register(Box::new(reconciliations.clone())).unwrap();
+        registry.register(Box::new(failures.clone())).unwrap();
+        registry.register(Box::new(reconcile_duration.clone())).unwrap();
+        Metrics { registry, reconciliations, failures, reconcile_duration }
+    }
+}
+
+impl Metrics {
+    /// Counts a reconciliation of `controller`. Its duration is observed when the returned timer
+    /// is dropped.
+    pub fn reconcile_started(&self, controller: &str) -> HistogramTimer {
+        self.reconciliations.with_label_values(&[controller]).inc();
+        self.reconcile_duration.with_label_values(&[controller]).start_timer()
+    }
+
+    pub fn reconcile_failed(&self, controller: &str, error: &Error) {
+        self.failures.with_label_values(&[controller, error.metric_label()]).inc();
+    }
+}
+
+/// Reports gauges that are derived from the reflector stores whenever the metrics are gathered.
+pub(in crate::controller) struct StoreCollector {
+    request_store: Store<SharedSecretRequest>,
+    shared_store: Store<SharedSecret>,
+    requests: IntGaugeVec,
+    shared_secrets: IntGaugeVec,
+    seconds_since_last_sync: GaugeVec,
+}
+
+impl StoreCollector {
+    pub fn new(request_store: Store<SharedSecretRequest>, shared_store: Store<SharedSecret>) -> Self {
+        StoreCollector {
+            request_store,
+            shared_store,
+            requests: IntGaugeVec::new(
+                Opts::new("shared_secret_requests", "Number of SharedSecretRequests by state").namespace(NAMESPACE),
+                &["state"],
+            ).unwrap(),
+            shared_secrets: IntGaugeVec::new(
+                Opts::new("shared_secrets", "Number of SharedSecrets by state").namespace(NAMESPACE),
+                &["state"],
+            ).unwrap(),
+            seconds_since_last_sync: GaugeVec::new(
+                Opts::new("shared_secret_request_seconds_since_last_sync", "Seconds since the local secret of a SharedSecretRequest was last confirmed to be in sync").namespace(NAMESPACE),
+                &["namespace", "name"],
+            ).unwrap(),
+        }
+    }
+}
+
+impl Collector for StoreCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.requests.desc().into_iter()
+            .chain(self.shared_secrets.desc())
+            .chain(self.seconds_since_last_sync.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let now = Utc::now();
+        let requests = self.request_store.state();
+
+        let mut request_states = BTreeMap::new();
+        for request in &requests {
+            let state = request.status.as_ref().map(|s| s.state).unwrap_or_default();
+            *request_states.entry(format!("{:?}", state)).or_insert(0) += 1;
+        }
+        let mut shared_states = BTreeMap::new();
+        for shared_secret in self.shared_store.state() {
+            let state = shared_secret.status.as_ref().map(|s| s.state).unwrap_or_default();
+            *shared_states.entry(format!("{:?}", state)).or_insert(0) += 1;
+        }
+
+        // reset, so that states and requests that are gone disappear from the output
+        self.requests.reset();
+        for (state, count) in request_states {
+            self.requests.with_label_values(&[&state]).set(count);
+        }
+        self.shared_secrets.reset();
+        for (state, count) in shared_states {
+            self.shared_secrets.with_label_values(&[&state]).set(count);
+        }
+        self.seconds_since_last_sync.reset();
+        for request in &requests {
+            if let Some(last_synced_at) = request.status.as_ref().and_then(|s| s.last_synced_at) {
+                let namespace = request.namespace().unwrap_or_default();
+                let elapsed = (now - last_synced_at).num_milliseconds() as f64 / 1000.;
+                self.seconds_since_last_sync.with_label_values(&[&namespace, &request.name_any()]).set(elapsed);
+            }
+        }
+
+        self.requests.collect().into_iter()
+            .chain(self.shared_secrets.collect())
+            .chain(self.seconds_since_last_sync.collect())
+            .collect()
+    }
+}
@@ -0,0 +1,30 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Secret;
+
+use crate::controller::PrefixFilter;
+
+/// Metadata that is never propagated, whatever the filter says. The last applied configuration
+/// of a secret contains its data in plain text.
+const NEVER_PROPAGATED: &[&str] = &["kubectl.kubernetes.io/last-applied-configuration"];
+
+impl PrefixFilter {
+    pub fn apply(&self, entries: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+        entries.iter()
+            .filter(|(key, _)| !NEVER_PROPAGATED.contains(&&key[..]))
+            .filter(|(key, _)| self.allow.iter().any(|prefix| key.starts_with(&prefix[..])))
+            .filter(|(key, _)| !self.deny.iter().any(|prefix| key.starts_with(&prefix[..])))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// The type of a secret, with the API server's default filled in.
+pub(in crate::controller) fn secret_type(secret: &Secret) -> &str {
+    secret.type_.as_deref().unwrap_or("Opaque")
+}
+
+/// Whether all `expected` entries are present in `actual`. Additional entries are ignored.
+pub(in crate::controller) fn contains_all(actual: &Option<BTreeMap<String, String>>, expected: &Option<BTreeMap<String, String>>) -> bool {
+    expected.iter().flatten().all(|(key, value)| actual.as_ref().and_then(|a| a.get(key)) == Some(value))
+}
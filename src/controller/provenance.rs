@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+
+use chrono::{SecondsFormat, Utc};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::ByteString;
+use sha2::{Digest, Sha256};
+
+use crate::controller::SharedSecret;
+use crate::controller::propagation::contains_all;
+
+pub(in crate::controller) const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+/// An annotation rather than a label, as request names may exceed the 63 characters allowed in
+/// label values.
+pub(in crate::controller) const REQUEST_ANNOTATION: &str = "sharedsecretctl.klauser.link/request";
+pub(in crate::controller) const SOURCE_NAMESPACE_ANNOTATION: &str = "sharedsecretctl.klauser.link/source-namespace";
+pub(in crate::controller) const SOURCE_NAME_ANNOTATION: &str = "sharedsecretctl.klauser.link/source-name";
+pub(in crate::controller) const SOURCE_RESOURCE_VERSION_ANNOTATION: &str = "sharedsecretctl.klauser.link/source-resource-version";
+pub(in crate::controller) const CONTENT_HASH_ANNOTATION: &str = "sharedsecretctl.klauser.link/content-hash";
+pub(in crate::controller) const LAST_SYNCED_AT_ANNOTATION: &str = "sharedsecretctl.klauser.link/last-synced-at";
+
+/// Labels and annotations added by [`stamp`], which mark a local secret as managed.
+pub(in crate::controller) const PROVENANCE_LABELS: &[&str] = &[MANAGED_BY_LABEL];
+pub(in crate::controller) const PROVENANCE_ANNOTATIONS: &[&str] = &[
+    REQUEST_ANNOTATION,
+    SOURCE_NAMESPACE_ANNOTATION,
+    SOURCE_NAME_ANNOTATION,
+    SOURCE_RESOURCE_VERSION_ANNOTATION,
+    CONTENT_HASH_ANNOTATION,
+    LAST_SYNCED_AT_ANNOTATION,
+];
+
+/// Annotations that change on every sync and therefore don't count towards being in sync.
+const VOLATILE_ANNOTATIONS: &[&str] = &[SOURCE_RESOURCE_VERSION_ANNOTATION, LAST_SYNCED_AT_ANNOTATION];
+
+/// SHA-256 over the keys and values of `data`, hex encoded. Keys and values are length-prefixed
+/// so that different maps never produce the same input.
+pub(in crate::controller) fn content_hash(data: &BTreeMap<String, ByteString>) -> String {
+    let mut hasher = Sha256::new();
+    for (key, value) in data {
+        hasher.update((key.len() as u64).to_be_bytes());
+        hasher.update(key.as_bytes());
+        hasher.update((value.0.len() as u64).to_be_bytes());
+        hasher.update(&value.0);
+    }
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Adds the provenance labels and annotations to a local secret managed by the request `request_name`
+/// of the controller `controller_name`.
+pub(in crate::controller) fn stamp(secret: &mut Secret, controller_name: &str, request_name: &str, shared_secret: &SharedSecret, remote_secret: &Secret) {
+    let hash = content_hash(secret.data.as_ref().unwrap_or(&Default::default()));
+    let labels = secret.metadata.labels.get_or_insert_with(Default::default);
+    labels.insert(MANAGED_BY_LABEL.to_string(), controller_name.to_string());
+
+    let annotations = secret.metadata.annotations.get_or_insert_with(Default::default);
+    annotations.insert(REQUEST_ANNOTATION.to_string(), request_name.to_string());
+    annotations.insert(SOURCE_NAMESPACE_ANNOTATION.to_string(), shared_secret.metadata.namespace.clone().unwrap_or_default());
+    annotations.insert(SOURCE_NAME_ANNOTATION.to_string(), shared_secret.metadata.name.clone().unwrap_or_default());
+    annotations.insert(SOURCE_RESOURCE_VERSION_ANNOTATION.to_string(), remote_secret.metadata.resource_version.clone().unwrap_or_default());
+    annotations.insert(CONTENT_HASH_ANNOTATION.to_string(), hash);
+    annotations.insert(LAST_SYNCED_AT_ANNOTATION.to_string(), Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true));
+}
+
+/// Whether `local` already matches `desired`. The content hash annotation is compared first;
+/// the data itself is still compared to catch manual edits of the local secret.
+pub(in crate::controller) fn is_in_sync(local: &Secret, desired: &Secret) -> bool {
+    let annotation = |secret: &Secret, key: &str| secret.metadata.annotations.as_ref().and_then(|a| a.get(key)).cloned();
+    if annotation(local, CONTENT_HASH_ANNOTATION) != annotation(desired, CONTENT_HASH_ANNOTATION) {
+        return false;
+    }
+
+    let stable_annotations = desired.metadata.annotations.as_ref().map(|annotations| {
+        annotations.iter()
+            .filter(|(key, _)| !VOLATILE_ANNOTATIONS.contains(&&key[..]))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    });
+    local.data.iter().flatten().eq(desired.data.iter().flatten())
+        && contains_all(&local.metadata.labels, &desired.metadata.labels)
+        && contains_all(&local.metadata.annotations, &stable_annotations)
+}
@@ -1,29 +1,48 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
-use std::time::Duration;
 
 use chrono::Utc;
-use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::api::core::v1::{Namespace, Secret};
+use k8s_openapi::ByteString;
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
 use kube::{Api, Resource, ResourceExt};
-use kube::api::{Patch, PatchParams, PostParams};
+use kube::api::{DeleteParams, Patch, PatchParams};
 use kube::runtime::finalizer;
 use kube::runtime::controller::Action;
+use kube::runtime::reflector::ObjectRef;
 use kube::runtime::events::{Event, EventType};
 use kube::runtime::finalizer::Event as Finalizer;
 use serde_json::json;
 use tracing::{debug, info, instrument, warn};
 
 use crate::{Error, Result};
-use crate::controller::{Context, CONTROLLER_NAME, SharedSecret, SharedSecretRequest, SharedSecretRequestState, SharedSecretRequestStatus, SharedSecretState};
+use crate::controller::access::AccessDecision;
+use crate::controller::conditions::{conditions_differ, merge_conditions};
+use crate::controller::keys::{describe_changes, KeyMappingError};
+use crate::controller::propagation::secret_type;
+use crate::controller::provenance::{is_in_sync, PROVENANCE_ANNOTATIONS, PROVENANCE_LABELS, stamp};
+use crate::controller::{AdoptionPolicy, Context, DeletionPolicy, reconcile_requested_at, record_reconcile_result, SharedSecret, SharedSecretRequest, SharedSecretRequestState, SharedSecretRequestStatus, SharedSecretState};
 
 static SHARED_SECRET_REQUEST_FINALIZER: &str = "sharedsecretctl.klauser.link/shared-secret-request";
 
 pub(in crate::controller) async fn reconcile_shared_secret_request(shared_secret_request: Arc<SharedSecretRequest>, ctx: Arc<Context>) -> Result<Action> {
     let ns = shared_secret_request.namespace().unwrap(); // we know that SharedSecret is namespaced
+    if !ctx.leadership.is_leader() {
+        // standbys keep their caches warm, but leave all writes to the leader
+        return Ok(Action::await_change());
+    }
+    if !ctx.watches_namespace(&ns) {
+        return Ok(Action::await_change());
+    }
+    let _permit = ctx.reconcile_permit().await;
     let shared_secret_requests = Api::<SharedSecretRequest>::namespaced(ctx.client.clone(), &ns);
 
     info!("Reconciling SharedSecretRequest \"{}\" in {}", shared_secret_request.name_any(), ns);
-    finalizer(
+    let _timer = ctx.metrics.reconcile_started("shared_secret_request");
+    let _progress = ctx.health.reconcile_started();
+    let obj_ref = ObjectRef::from_obj(&*shared_secret_request);
+    let previous_error = shared_secret_request.status.as_ref().and_then(|s| s.last_error.clone());
+    let result = finalizer(
         &shared_secret_requests,
         SHARED_SECRET_REQUEST_FINALIZER,
         shared_secret_request,
@@ -34,7 +53,10 @@ pub(in crate::controller) async fn reconcile_shared_secret_request(shared_secret
             }
         }
     ).await
-        .map_err(|e| Error::FinalizerError(Box::new(e)))
+        .map_err(|e| Error::FinalizerError(Box::new(e)));
+
+    record_reconcile_result(&ctx, &shared_secret_requests, &ctx.request_backoff, "shared_secret_request", &obj_ref, previous_error, &result).await;
+    result
 }
 
 impl SharedSecretRequest {
@@ -50,67 +72,192 @@ impl SharedSecretRequest {
         let remote_secrets: Api<Secret> = Api::namespaced(client.clone(), remote_ns);
         let local_secrets: Api<Secret> = Api::namespaced(client.clone(), local_ns);
 
+        let events = ctx.event_recorder(self.object_ref(&()));
+
+        // Check that no other request writes to the same local secret
+        if let Some(winner) = self.local_secret_winner(&ctx).await? {
+            let note = format!("SharedSecretRequest \"{}\" already writes to local secret \"{}\"", winner.name_any(), self.local_secret_name());
+            debug!("{}", note);
+            if self.status.as_ref().map(|s| s.state) != Some(SharedSecretRequestState::Conflict) {
+                events.publish(Event {
+                    action: "Validating".into(),
+                    reason: "LocalSecretConflict".into(),
+                    note: Some(note),
+                    secondary: Some(winner.object_ref(&())),
+                    type_: EventType::Warning,
+                }).await?;
+            }
+            self.update_status(&ctx, SharedSecretRequestState::Conflict).await?;
+
+            return Ok(ctx.resync.requeue(self.spec.resync_interval_seconds));
+        }
+
+        // Release the previously managed local secret if the request now points elsewhere. The
+        // deletion policy applies as if the request was deleted: the old secret is either deleted
+        // or loses its owner reference, so that it doesn't stay tied to this request.
+        let previous_local_secret = self.status.as_ref().and_then(|s| s.managed_local_secret.as_deref());
+        if let Some(previous_local_secret) = previous_local_secret.filter(|previous| *previous != self.local_secret_name()) {
+            info!("SharedSecretRequest \"{}\" in {} no longer targets local secret \"{}\". Releasing it...", name, local_ns, previous_local_secret);
+            self.release_local_secret(&ctx, previous_local_secret).await?;
+            let mut new_status = self.status.clone().unwrap_or_default();
+            new_status.managed_local_secret = None;
+            self.write_status(&ctx, new_status).await?;
+
+            // the status update triggers another reconciliation that continues from there
+            return Ok(Action::await_change());
+        }
+
+        // Shared secrets outside of the watched namespaces are not validated by this controller
+        if !ctx.watches_namespace(remote_ns) {
+            debug!("SharedSecret \"{}.{}\" is outside of the watched namespaces", self.spec.shared_secret.name, remote_ns);
+            self.update_status(&ctx, SharedSecretRequestState::SharedSecretInvalid).await?;
+
+            return Ok(ctx.resync.requeue(self.spec.resync_interval_seconds));
+        }
+
         // Check that shared secret exists
         let Some(shared_secret) = shared_secrets.get_opt(&self.spec.shared_secret.name).await? else {
             debug!("SharedSecret \"{}.{}\" is missing", self.spec.shared_secret.name, remote_ns);
-            self.update_status(&ctx, SharedSecretRequestStatus {
-                state: SharedSecretRequestState::SharedSecretMissing,
-                last_updated_at: Some(Utc::now()),
-            }).await?;
+            self.update_status(&ctx, SharedSecretRequestState::SharedSecretMissing).await?;
 
-            return Ok(Action::requeue(Duration::from_secs(5 * 60)));
+            return Ok(ctx.resync.requeue(self.spec.resync_interval_seconds));
         };
 
         // Check that shared secret is valid
-        let remote_state = shared_secret.status.map(|s| s.state);
+        let remote_state = shared_secret.status.as_ref().map(|s| s.state);
         if remote_state != Some(SharedSecretState::Valid) {
             debug!("SharedSecret \"{}.{}\" is in state {:?}, expecting {:?} instead", self.spec.shared_secret.name, remote_ns, remote_state, SharedSecretState::Valid);
-            self.update_status(&ctx, SharedSecretRequestStatus {
-                state: SharedSecretRequestState::SharedSecretInvalid,
-                last_updated_at: Some(Utc::now()),
-            }).await?;
+            self.update_status(&ctx, SharedSecretRequestState::SharedSecretInvalid).await?;
+
+            return Ok(ctx.resync.requeue(self.spec.resync_interval_seconds));
+        }
 
-            return Ok(Action::requeue(Duration::from_secs(5 * 60)));
+        // Check that this namespace is permitted to read the shared secret
+        let previous_state = self.status.as_ref().map(|s| s.state);
+        let access = self.check_access(&ctx, &shared_secret).await?;
+        if let Some(denied_state) = access.denied_state() {
+            debug!("SharedSecretRequest \"{}\" in {} may not read SharedSecret \"{}.{}\": {:?}", name, local_ns, self.spec.shared_secret.name, remote_ns, access);
+            let revoked = self.revoke(&ctx, &shared_secret).await?;
+            let state = if access == AccessDecision::Expired {
+                denied_state
+            } else if revoked || previous_state == Some(SharedSecretRequestState::Revoked) {
+                SharedSecretRequestState::Revoked
+            } else {
+                denied_state
+            };
+            if (!revoked || access == AccessDecision::Expired) && previous_state != Some(state) {
+                self.publish_access_event(&ctx, &shared_secret, access).await?;
+            }
+            let mut new_status = self.status.clone().unwrap_or_default().with_state(state, self.metadata.generation);
+            if revoked {
+                new_status.managed_local_secret = None;
+            }
+            self.write_status(&ctx, new_status).await?;
+
+            return Ok(ctx.resync.requeue(self.spec.resync_interval_seconds));
+        }
+        let expires_at = match access {
+            AccessDecision::Permitted { expires_at } => expires_at,
+            _ => None,
+        };
+        if shared_secret.spec.requires_approval && previous_state == Some(SharedSecretRequestState::PendingApproval) {
+            events.publish(Event {
+                action: "Validating".into(),
+                reason: "Approved".into(),
+                note: Some(format!("SharedSecret \"{}\" in {} approved this request", self.spec.shared_secret.name, remote_ns)),
+                secondary: Some(shared_secret.object_ref(&())),
+                type_: EventType::Normal,
+            }).await?;
         }
 
-        // TODO: check that this namespace is permitted to read the shared secret
+        // A suspended request keeps its local secret as it is. This check comes after the access
+        // checks on purpose: suspending a request must not prevent revocation.
+        if self.spec.suspend {
+            debug!("SharedSecretRequest \"{}\" in {} is suspended", name, local_ns);
+            self.update_status(&ctx, SharedSecretRequestState::Suspended).await?;
+
+            return Ok(ctx.resync.requeue_until(self.spec.resync_interval_seconds, expires_at));
+        }
 
         // Check that the remote secret exists
         let Some(remote_secret) = remote_secrets.get_opt(&shared_secret.spec.secret_name).await? else {
-            self.update_status(&ctx, SharedSecretRequestStatus {
-                state: SharedSecretRequestState::SharedSecretInvalid,
-                last_updated_at: Some(Utc::now()),
-            }).await?;
+            self.update_status(&ctx, SharedSecretRequestState::SharedSecretInvalid).await?;
 
-            return Ok(Action::requeue(Duration::from_secs(5 * 60)));
+            return Ok(ctx.resync.requeue_until(self.spec.resync_interval_seconds, expires_at));
         };
 
-        let events = ctx.event_recorder(self.object_ref(&()));
+        // Select and rename the keys that the local secret should contain
+        let exported_data = shared_secret.spec.export.apply(remote_secret.data.as_ref().unwrap_or(&Default::default()));
+        let data = match self.spec.keys.apply(&exported_data) {
+            Ok(data) => data,
+            Err(e) => {
+                let state = match e {
+                    KeyMappingError::MissingKeys(_) => SharedSecretRequestState::RequiredKeyMissing,
+                    KeyMappingError::InvalidRegex(_) | KeyMappingError::DuplicateTarget(_) => SharedSecretRequestState::InvalidSpec,
+                };
+                debug!("Cannot map keys of SharedSecret \"{}.{}\" for SharedSecretRequest \"{}\" in {}: {}", self.spec.shared_secret.name, remote_ns, name, local_ns, e);
+                if previous_state != Some(state) {
+                    events.publish(Event {
+                        action: "Synchronizing".into(),
+                        reason: format!("{:?}", state),
+                        note: Some(e.to_string()),
+                        secondary: Some(shared_secret.object_ref(&())),
+                        type_: EventType::Warning,
+                    }).await?;
+                }
+                self.update_status(&ctx, state).await?;
+
+                return Ok(ctx.resync.requeue_until(self.spec.resync_interval_seconds, expires_at));
+            }
+        };
 
         // Create or update local secret
-        let local_secret_name = self.spec.local_secret_name.as_ref().map(|s| &s[..]).unwrap_or(name);
-        match local_secrets.get_opt(local_secret_name).await? {
+        let local_secret_name = self.local_secret_name();
+        let desired = self.desired_local_secret(&ctx, &shared_secret, &remote_secret, data);
+        let resync_requested = reconcile_requested_at(self)
+            .filter(|requested_at| self.status.as_ref().and_then(|s| s.last_handled_reconcile_at.as_deref()) != Some(*requested_at));
+        let local_secret = match local_secrets.get_opt(local_secret_name).await? {
+            Some(local_secret) if !self.owns(&local_secret) => {
+                let has_data = local_secret.data.as_ref().map(|d| !d.is_empty()).unwrap_or(false);
+                let refusal = match self.spec.adoption {
+                    AdoptionPolicy::Never => Some((SharedSecretRequestState::Conflict, format!("Local secret \"{}\" already exists and is not managed by this SharedSecretRequest; set adoption to adopt it", local_secret_name))),
+                    AdoptionPolicy::IfEmpty if has_data => Some((SharedSecretRequestState::Conflict, format!("Local secret \"{}\" already exists and contains data; not adopting it", local_secret_name))),
+                    _ if secret_type(&local_secret) != secret_type(&desired) => Some((SharedSecretRequestState::TypeMismatch, format!("Local secret \"{}\" has type {} but the shared secret has type {}; not adopting it", local_secret_name, secret_type(&local_secret), secret_type(&desired)))),
+                    _ => None,
+                };
+                if let Some((state, note)) = refusal {
+                    debug!("{}", note);
+                    if previous_state != Some(state) {
+                        events.publish(Event {
+                            action: "Synchronizing".into(),
+                            reason: format!("{:?}", state),
+                            note: Some(note),
+                            secondary: Some(local_secret.object_ref(&())),
+                            type_: EventType::Warning,
+                        }).await?;
+                    }
+                    self.update_status(&ctx, state).await?;
+
+                    return Ok(ctx.resync.requeue_until(self.spec.resync_interval_seconds, expires_at));
+                }
+
+                info!("Adopting local secret \"{}\" for SharedSecretRequest \"{}\" in {}", local_secret_name, name, local_ns);
+                let adopted = self.adopt(&ctx, &local_secret, &desired).await?;
+                events.publish(Event {
+                    action: "Adopting".into(),
+                    reason: "LocalSecretExists".into(),
+                    note: Some(format!("Adopted existing local secret \"{}\" (adoption: {:?})", local_secret_name, self.spec.adoption)),
+                    secondary: Some(adopted.object_ref(&())),
+                    type_: EventType::Normal,
+                }).await?;
+                Some(adopted)
+            }
+            local_secret => local_secret,
+        };
+        let local_secret = match local_secret {
             None => {
                 info!("Local secret \"{}\" for SharedSecretRequest \"{}\" in {} does not exist. Creating...", local_secret_name, name, local_ns);
-                let local_secret = Secret {
-                    metadata: ObjectMeta {
-                        name: Some(local_secret_name.to_string()),
-                        namespace: Some(local_ns.to_string()),
-                        owner_references: Some(vec![OwnerReference {
-                            api_version: "sharedsecretctl.klauser.link/v1".to_string(),
-                            kind: "SharedSecretRequest".to_string(),
-                            name: name.to_string(),
-                            uid: self.metadata.uid.clone().unwrap(),
-                            ..Default::default()
-                        }]),
-                        ..Default::default()
-                    },
-                    data: remote_secret.data.clone(),
-                    ..Default::default()
-                };
-                let mut ps = PostParams::default();
-                ps.field_manager = Some(CONTROLLER_NAME.to_string());
-                let created = local_secrets.create(&ps, &local_secret).await?;
+                let created = self.apply_local_secret(&ctx, &desired).await?;
                 events.publish(Event {
                     action: "Creating".into(),
                     reason: "LocalSecretMissing".into(),
@@ -118,41 +265,298 @@ impl SharedSecretRequest {
                     secondary: Some(created.object_ref(&())),
                     type_: EventType::Normal,
                 }).await?;
+                created
             }
-            Some(local_secret) if local_secret.data != remote_secret.data => {
+            Some(local_secret) if secret_type(&local_secret) != secret_type(&desired) => {
+                let note = format!("Local secret \"{}\" has type {} but the shared secret has type {}", local_secret_name, secret_type(&local_secret), secret_type(&desired));
+                // the type of a secret is immutable, so the local secret has to be replaced
+                info!("{}. Replacing it...", note);
+                local_secrets.delete(local_secret_name, &DeleteParams::default()).await?;
+                let created = self.apply_local_secret(&ctx, &desired).await?;
+                events.publish(Event {
+                    action: "Replacing".into(),
+                    reason: "LocalSecretTypeChanged".into(),
+                    note: Some(note),
+                    secondary: Some(created.object_ref(&())),
+                    type_: EventType::Normal,
+                }).await?;
+                created
+            }
+            Some(local_secret) if resync_requested.is_some() || !is_in_sync(&local_secret, &desired) => {
                 info!("Local secret \"{}\" for SharedSecretRequest \"{}\" in {} is out of sync. Updating...", local_secret_name, name, local_ns);
-                let local_secret_patch = Patch::Merge(Secret {
-                    data: remote_secret.data.clone(),
-                    ..Default::default()
-                });
-                let ps = PatchParams::apply(CONTROLLER_NAME);
-                let updated = local_secrets.patch(local_secret_name, &ps, &local_secret_patch).await?;
+                let updated = self.apply_local_secret(&ctx, &desired).await?;
+                let changes = describe_changes(
+                    local_secret.data.as_ref().unwrap_or(&Default::default()),
+                    updated.data.as_ref().unwrap_or(&Default::default()),
+                );
                 events.publish(Event {
                     action: "Updating".into(),
                     reason: "LocalSecretOutdated".into(),
-                    note: None,
+                    note: Some(changes),
                     secondary: Some(updated.object_ref(&())),
                     type_: EventType::Normal,
                 }).await?;
+                updated
             },
-            _ => {
+            Some(local_secret) => {
                 info!("SharedSecretRequest \"{}\" in {} is still synchronized. Nothing to do.", name, local_ns);
+                local_secret
             }
-        }
+        };
+
+        // Roll out on every sync rather than only after a change, so that a rollout that failed
+        // is retried. Workloads that already run with the current content are skipped.
+        self.trigger_rollouts(&ctx, &local_secret).await;
 
         // Mark ourselves as synchronized
-        if self.status.as_ref().map(|s| s.state) != Some(SharedSecretRequestState::Synchronized) {
-            self.update_status(&ctx, SharedSecretRequestStatus {
-                state: SharedSecretRequestState::Synchronized,
-                last_updated_at: Some(Utc::now()),
-            }).await?;
+        let mut new_status = self.status.clone().unwrap_or_default().with_state(SharedSecretRequestState::Synchronized, self.metadata.generation);
+        new_status.managed_local_secret = Some(local_secret_name.to_string());
+        // Every status update triggers another reconciliation, which must not bump the timestamp
+        // again. Hence, it is only bumped if the last sync is older than the minimum interval.
+        let now = Utc::now();
+        let recently_synced = new_status.last_synced_at
+            .map(|t| (now - t).to_std().unwrap_or_default() < ctx.resync.min_interval)
+            .unwrap_or(false);
+        if !recently_synced {
+            new_status.last_synced_at = Some(now);
+        }
+        if let Some(requested_at) = resync_requested {
+            new_status.last_handled_reconcile_at = Some(requested_at.to_string());
+        }
+        self.write_status(&ctx, new_status).await?;
+
+        // If no events were received, check back after the resync interval (or when access expires)
+        Ok(ctx.resync.requeue_until(self.spec.resync_interval_seconds, expires_at))
+    }
+
+    pub(in crate::controller) fn local_secret_name(&self) -> &str {
+        self.spec.local_secret_name.as_deref()
+            .unwrap_or_else(|| self.metadata.name.as_ref().expect("SharedSecretRequest to have a name"))
+    }
+
+    /// The local secret as it should look like, given the source secret and the mapped data.
+    fn desired_local_secret(&self, ctx: &Context, shared_secret: &SharedSecret, remote_secret: &Secret, data: BTreeMap<String, ByteString>) -> Secret {
+        let propagation = &self.spec.metadata_propagation;
+        let labels = propagation.labels.apply(remote_secret.metadata.labels.as_ref().unwrap_or(&Default::default()));
+        let annotations = propagation.annotations.apply(remote_secret.metadata.annotations.as_ref().unwrap_or(&Default::default()));
+        let mut desired = Secret {
+            metadata: ObjectMeta {
+                name: Some(self.local_secret_name().to_string()),
+                namespace: self.metadata.namespace.clone(),
+                labels: Some(labels).filter(|l| !l.is_empty()),
+                annotations: Some(annotations).filter(|a| !a.is_empty()),
+                owner_references: Some(vec![OwnerReference {
+                    api_version: "sharedsecretctl.klauser.link/v1".to_string(),
+                    kind: "SharedSecretRequest".to_string(),
+                    name: self.name_any(),
+                    uid: self.metadata.uid.clone().unwrap(),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+            type_: remote_secret.type_.clone(),
+            data: Some(data),
+            ..Default::default()
+        };
+        stamp(&mut desired, &ctx.controller_name, &self.name_any(), shared_secret, remote_secret);
+        desired
+    }
+
+    /// Adds the owner reference of this request to an existing local secret.
+    async fn adopt(&self, ctx: &Context, local_secret: &Secret, desired: &Secret) -> Result<Secret> {
+        // we know that SharedSecretRequest is namespaced
+        let local_ns = &self.meta().namespace.as_ref().unwrap()[..];
+        let local_secrets: Api<Secret> = Api::namespaced(ctx.client.clone(), local_ns);
+        let owner_references: Vec<_> = local_secret.owner_references().iter()
+            .chain(desired.owner_references())
+            .cloned()
+            .collect();
+        let patch = Patch::Merge(json!({
+            "metadata": {
+                "resourceVersion": local_secret.resource_version(),
+                "ownerReferences": owner_references,
+            }
+        }));
+        Ok(local_secrets.patch(&local_secret.name_any(), &PatchParams::apply(&ctx.controller_name), &patch).await?)
+    }
+
+    /// Converges the local secret to `desired` using server-side apply.
+    async fn apply_local_secret(&self, ctx: &Context, desired: &Secret) -> Result<Secret> {
+        // we know that SharedSecretRequest is namespaced
+        let local_ns = &self.meta().namespace.as_ref().unwrap()[..];
+        let local_secrets: Api<Secret> = Api::namespaced(ctx.client.clone(), local_ns);
+        let local_secret_name = self.local_secret_name();
+
+        let ps = PatchParams::apply(&ctx.controller_name).force();
+        let applied = local_secrets.patch(local_secret_name, &ps, &Patch::Apply(desired)).await?;
+
+        // Keys that are owned by other field managers (e.g. data of an adopted secret) survive
+        // server-side apply and have to be removed explicitly.
+        let desired_data = desired.data.as_ref();
+        let stale_keys: serde_json::Map<String, serde_json::Value> = applied.data.iter().flatten()
+            .filter(|(key, _)| !desired_data.map(|d| d.contains_key(*key)).unwrap_or(false))
+            .map(|(key, _)| (key.clone(), serde_json::Value::Null))
+            .collect();
+        if stale_keys.is_empty() {
+            return Ok(applied);
+        }
+        debug!("Removing {} stale keys from local secret \"{}\" in {}", stale_keys.len(), local_secret_name, local_ns);
+        let patch = Patch::Merge(json!({
+            "metadata": { "resourceVersion": applied.resource_version() },
+            "data": stale_keys,
+        }));
+        Ok(local_secrets.patch(local_secret_name, &PatchParams::apply(&ctx.controller_name), &patch).await?)
+    }
+
+    /// If other requests in this namespace write to the same local secret, the one that owns the
+    /// local secret wins, so that a request pointed at a secret another request already manages
+    /// never takes it over. Otherwise, the oldest one wins (ties are broken by name). Returns the
+    /// winner if that is not this request.
+    async fn local_secret_winner(&self, ctx: &Context) -> Result<Option<Arc<SharedSecretRequest>>> {
+        let others: Vec<_> = ctx.request_store.state().into_iter()
+            .filter(|other| other.metadata.uid != self.metadata.uid)
+            .filter(|other| other.metadata.namespace == self.metadata.namespace)
+            .filter(|other| other.metadata.deletion_timestamp.is_none())
+            .filter(|other| other.local_secret_name() == self.local_secret_name())
+            .collect();
+        if others.is_empty() {
+            return Ok(None);
+        }
+
+        // we know that SharedSecretRequest is namespaced
+        let local_ns = &self.meta().namespace.as_ref().unwrap()[..];
+        let local_secrets: Api<Secret> = Api::namespaced(ctx.client.clone(), local_ns);
+        let local_secret = local_secrets.get_opt(self.local_secret_name()).await?;
+        let owns = |request: &SharedSecretRequest| local_secret.as_ref().map(|secret| request.owns(secret)).unwrap_or(false);
+        let winner = others.into_iter()
+            .chain(std::iter::once(Arc::new(self.clone())))
+            .min_by(|a, b| {
+                (!owns(a), &a.metadata.creation_timestamp, a.metadata.name.as_ref())
+                    .cmp(&(!owns(b), &b.metadata.creation_timestamp, b.metadata.name.as_ref()))
+            })
+            .filter(|winner| winner.metadata.uid != self.metadata.uid);
+        Ok(winner)
+    }
+
+    fn owns(&self, secret: &Secret) -> bool {
+        secret.owner_references().iter().any(|owner| Some(&owner.uid) == self.metadata.uid.as_ref())
+    }
+
+    /// Deletes the local secret because access to `shared_secret` was revoked. A local secret
+    /// that is not owned by this request was never written by it and is left alone. Returns
+    /// `true` if the local secret was deleted.
+    async fn revoke(&self, ctx: &Context, shared_secret: &SharedSecret) -> Result<bool> {
+        // we know that SharedSecretRequest is namespaced
+        let local_ns = &self.meta().namespace.as_ref().unwrap()[..];
+        let remote_ns = &shared_secret.meta().namespace.as_ref().unwrap()[..];
+        let local_secret_name = self.local_secret_name();
+        let local_secrets: Api<Secret> = Api::namespaced(ctx.client.clone(), local_ns);
+
+        let Some(local_secret) = local_secrets.get_opt(local_secret_name).await? else {
+            return Ok(false);
+        };
+        if !self.owns(&local_secret) {
+            return Ok(false);
+        }
+        info!("Deleting local secret \"{}\" in {} because access to SharedSecret \"{}.{}\" was revoked", local_secret_name, local_ns, shared_secret.name_any(), remote_ns);
+        local_secrets.delete(local_secret_name, &DeleteParams::default()).await?;
+
+        let note = format!("Access of SharedSecretRequest \"{}\" in {} to SharedSecret \"{}\" in {} was revoked; removed local secret \"{}\"", self.name_any(), local_ns, shared_secret.name_any(), remote_ns, local_secret_name);
+        ctx.event_recorder(self.object_ref(&())).publish(Event {
+            action: "Revoking".into(),
+            reason: "AccessRevoked".into(),
+            note: Some(note.clone()),
+            secondary: Some(shared_secret.object_ref(&())),
+            type_: EventType::Warning,
+        }).await?;
+        ctx.event_recorder(shared_secret.object_ref(&())).publish(Event {
+            action: "Revoking".into(),
+            reason: "AccessRevoked".into(),
+            note: Some(note),
+            secondary: Some(self.object_ref(&())),
+            type_: EventType::Normal,
+        }).await?;
+
+        Ok(true)
+    }
+
+    async fn check_access(&self, ctx: &Context, shared_secret: &SharedSecret) -> Result<AccessDecision> {
+        // we know that SharedSecretRequest is namespaced
+        let local_ns = &self.meta().namespace.as_ref().unwrap()[..];
+        if shared_secret.metadata.namespace.as_deref() == Some(local_ns) {
+            return Ok(AccessDecision::Permitted { expires_at: None });
         }
 
-        // If no events were received, check back every 5 minutes
-        Ok(Action::requeue(Duration::from_secs(5 * 60)))
+        let policy = &shared_secret.spec.access_policy;
+        let namespace_labels = if policy.requires_namespace_labels() {
+            let namespaces: Api<Namespace> = Api::all(ctx.client.clone());
+            namespaces.get_opt(local_ns).await?
+                .and_then(|namespace| namespace.metadata.labels)
+                .unwrap_or_default()
+        } else {
+            Default::default()
+        };
+        match policy.evaluate(local_ns, &namespace_labels, Utc::now()) {
+            AccessDecision::Permitted { expires_at } => Ok(shared_secret.approval_decision(self, expires_at)),
+            denied => Ok(denied),
+        }
     }
 
-    async fn update_status(&self, ctx: &Context, new_status: SharedSecretRequestStatus) -> Result<()> {
+    async fn publish_access_event(&self, ctx: &Context, shared_secret: &SharedSecret, access: AccessDecision) -> Result<()> {
+        // we know that both resources are namespaced
+        let local_ns = &self.meta().namespace.as_ref().unwrap()[..];
+        let remote_ns = &shared_secret.meta().namespace.as_ref().unwrap()[..];
+        let events = ctx.event_recorder(self.object_ref(&()));
+        match access {
+            AccessDecision::Permitted { .. } => {}
+            AccessDecision::Expired => events.publish(Event {
+                action: "Validating".into(),
+                reason: "GrantExpired".into(),
+                note: Some(format!("The grant for namespace {} in the access_policy of SharedSecret \"{}\" in {} has expired", local_ns, shared_secret.name_any(), remote_ns)),
+                secondary: Some(shared_secret.object_ref(&())),
+                type_: EventType::Warning,
+            }).await?,
+            AccessDecision::Forbidden => events.publish(Event {
+                action: "Validating".into(),
+                reason: "AccessDenied".into(),
+                note: Some(format!("Namespace {} is not permitted by the access_policy of SharedSecret \"{}\" in {}", local_ns, shared_secret.name_any(), remote_ns)),
+                secondary: Some(shared_secret.object_ref(&())),
+                type_: EventType::Warning,
+            }).await?,
+            AccessDecision::PendingApproval => {
+                let note = format!("SharedSecretRequest \"{}\" in {} awaits approval; add it to approved_requests of SharedSecret \"{}\" in {}", self.name_any(), local_ns, shared_secret.name_any(), remote_ns);
+                events.publish(Event {
+                    action: "Validating".into(),
+                    reason: "ApprovalRequired".into(),
+                    note: Some(note.clone()),
+                    secondary: Some(shared_secret.object_ref(&())),
+                    type_: EventType::Normal,
+                }).await?;
+                ctx.event_recorder(shared_secret.object_ref(&())).publish(Event {
+                    action: "Validating".into(),
+                    reason: "ApprovalRequired".into(),
+                    note: Some(note),
+                    secondary: Some(self.object_ref(&())),
+                    type_: EventType::Normal,
+                }).await?;
+            }
+            AccessDecision::Denied => events.publish(Event {
+                action: "Validating".into(),
+                reason: "ApprovalDenied".into(),
+                note: Some(format!("SharedSecretRequest \"{}\" is listed in denied_requests of SharedSecret \"{}\" in {}", self.name_any(), shared_secret.name_any(), remote_ns)),
+                secondary: Some(shared_secret.object_ref(&())),
+                type_: EventType::Warning,
+            }).await?,
+        }
+        Ok(())
+    }
+
+    async fn update_status(&self, ctx: &Context, state: SharedSecretRequestState) -> Result<()> {
+        let new_status = self.status.clone().unwrap_or_default().with_state(state, self.metadata.generation);
+        self.write_status(ctx, new_status).await
+    }
+
+    async fn write_status(&self, ctx: &Context, new_status: SharedSecretRequestStatus) -> Result<()> {
         if self.status.as_ref().map(|s| s.update_required(&new_status)) == Some(false) {
             debug!(new_status=?&new_status, "Not updating status of SharedSecretRequest because it is unchanged.");
             return Ok(());
@@ -164,36 +568,119 @@ impl SharedSecretRequest {
 
         let shared_secret_requests: Api<SharedSecretRequest> = Api::namespaced(ctx.client.clone(), ns);
         let new_status_patch = Patch::Apply(json!({
-        "apiVersion": "sharedsecretctl.klauser.link/v1",
-        "kind": "SharedSecretRequest",
-        "status": new_status
-    }));
-        let ps = PatchParams::apply(CONTROLLER_NAME).force();
+            "apiVersion": "sharedsecretctl.klauser.link/v1",
+            "kind": "SharedSecretRequest",
+            "status": new_status
+        }));
+        let ps = PatchParams::apply(&ctx.controller_name).force();
         shared_secret_requests.patch_status(name, &ps, &new_status_patch)
             .await
-            .map_err(Error::KubeError)?;
+            .map_err(Error::from)?;
 
         Ok(())
     }
 
     async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action> {
+        self.release_local_secret(&ctx, self.local_secret_name()).await?;
+
+        // If no events were received, check back after the resync interval
+        Ok(ctx.resync.requeue(self.spec.resync_interval_seconds))
+    }
+
+    /// Applies the deletion policy to the local secret `local_secret_name`, provided that it is
+    /// owned by this request. Afterwards, the secret no longer has an owner reference to this
+    /// request, whichever the policy.
+    async fn release_local_secret(&self, ctx: &Context, local_secret_name: &str) -> Result<()> {
         // we know that SharedSecretRequest is namespaced
-        let _ns = &self.meta().namespace.as_ref().unwrap()[..];
-        let _name = &self.metadata.name.as_ref().expect("SharedSecretRequest to have a name")[..];
-        let _client = ctx.client.clone();
+        let ns = &self.meta().namespace.as_ref().unwrap()[..];
+        let name = &self.metadata.name.as_ref().expect("SharedSecretRequest to have a name")[..];
+        let local_secrets: Api<Secret> = Api::namespaced(ctx.client.clone(), ns);
 
-        // If no events were received, check back every 5 minutes
-        Ok(Action::requeue(Duration::from_secs(5 * 60)))
+        let Some(local_secret) = local_secrets.get_opt(local_secret_name).await? else {
+            return Ok(());
+        };
+        if !self.owns(&local_secret) {
+            debug!("Local secret \"{}\" in {} is not owned by SharedSecretRequest \"{}\". Leaving it alone.", local_secret_name, ns, name);
+            return Ok(());
+        }
+
+        let events = ctx.event_recorder(self.object_ref(&()));
+        match self.spec.deletion_policy {
+            DeletionPolicy::Delete => {
+                info!("Deleting local secret \"{}\" of SharedSecretRequest \"{}\" in {}", local_secret_name, name, ns);
+                local_secrets.delete(local_secret_name, &DeleteParams::default()).await?;
+                events.publish(Event {
+                    action: "Deleting".into(),
+                    reason: "DeletionPolicy".into(),
+                    note: Some(format!("Deleted local secret \"{}\"", local_secret_name)),
+                    secondary: Some(local_secret.object_ref(&())),
+                    type_: EventType::Normal,
+                }).await?;
+            }
+            DeletionPolicy::Orphan | DeletionPolicy::Retain => {
+                let orphan = self.spec.deletion_policy == DeletionPolicy::Orphan;
+                info!("{} local secret \"{}\" of SharedSecretRequest \"{}\" in {}", if orphan { "Orphaning" } else { "Retaining" }, local_secret_name, name, ns);
+                let owner_references: Vec<_> = local_secret.owner_references().iter()
+                    .filter(|owner| Some(&owner.uid) != self.metadata.uid.as_ref())
+                    .cloned()
+                    .collect();
+                let mut metadata = json!({
+                    "resourceVersion": local_secret.resource_version(),
+                    "ownerReferences": owner_references,
+                });
+                if orphan {
+                    let remove = |keys: &[&str]| keys.iter()
+                        .map(|key| (key.to_string(), serde_json::Value::Null))
+                        .collect::<serde_json::Map<_, _>>();
+                    metadata["labels"] = remove(PROVENANCE_LABELS).into();
+                    metadata["annotations"] = remove(PROVENANCE_ANNOTATIONS).into();
+                }
+                local_secrets.patch(local_secret_name, &PatchParams::apply(&ctx.controller_name), &Patch::Merge(json!({ "metadata": metadata }))).await?;
+                events.publish(Event {
+                    action: if orphan { "Orphaning" } else { "Retaining" }.into(),
+                    reason: "DeletionPolicy".into(),
+                    note: Some(if orphan {
+                        format!("Released local secret \"{}\"; it is no longer managed", local_secret_name)
+                    } else {
+                        format!("Retained local secret \"{}\"; it is no longer garbage collected with the SharedSecretRequest", local_secret_name)
+                    }),
+                    secondary: Some(local_secret.object_ref(&())),
+                    type_: EventType::Normal,
+                }).await?;
+            }
+        }
+
+        Ok(())
     }
 }
 
 impl SharedSecretRequestStatus {
+    fn with_state(self, state: SharedSecretRequestState, generation: Option<i64>) -> Self {
+        SharedSecretRequestStatus {
+            state,
+            last_updated_at: Some(Utc::now()),
+            last_synced_at: self.last_synced_at,
+            managed_local_secret: self.managed_local_secret,
+            last_handled_reconcile_at: self.last_handled_reconcile_at,
+            last_error: self.last_error,
+            observed_generation: generation,
+            conditions: merge_conditions(&self.conditions, state.desired_conditions(), generation),
+        }
+    }
+
     fn update_required(&self, other: &SharedSecretRequestStatus) -> bool {
         self.state != other.state
+            || self.managed_local_secret != other.managed_local_secret
+            || self.last_synced_at != other.last_synced_at
+            || self.last_handled_reconcile_at != other.last_handled_reconcile_at
+            || self.last_error != other.last_error
+            || self.observed_generation != other.observed_generation
+            || conditions_differ(&self.conditions, &other.conditions)
     }
 }
 
-pub(in crate::controller) fn shared_secret_request_error_policy(_doc: Arc<SharedSecretRequest>, error: &Error, _ctx: Arc<Context>) -> Action {
-    warn!("SharedSecretRequest reconcile failed: {:?}", error);
-    Action::requeue(Duration::from_secs(5 * 60))
+pub(in crate::controller) fn shared_secret_request_error_policy(shared_secret_request: Arc<SharedSecretRequest>, error: &Error, ctx: Arc<Context>) -> Action {
+    let delay = ctx.request_backoff.next_delay(&shared_secret_request, error);
+    warn!("SharedSecretRequest reconcile failed, retrying in {:?}: {:?}", delay, error);
+    Action::requeue(delay)
 }
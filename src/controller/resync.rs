@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use kube::runtime::controller::Action;
+use rand::Rng;
+
+/// Controller-wide bounds for the interval in which objects are reconciled even if no change was
+/// observed.
+#[derive(Clone, Debug)]
+pub(in crate::controller) struct ResyncSettings {
+    /// Interval for objects that don't set `resync_interval_seconds`.
+    pub default_interval: Duration,
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    /// Maximum deviation from the interval, as a fraction of the interval. Spreads out the
+    /// requeues of objects that were reconciled at the same time (e.g. on startup).
+    pub jitter: f64,
+}
+
+impl ResyncSettings {
+    /// The interval requested by an object, clamped to the bounds.
+    pub fn interval(&self, requested_seconds: Option<u64>) -> Duration {
+        requested_seconds.map(Duration::from_secs)
+            .unwrap_or(self.default_interval)
+            .clamp(self.min_interval, self.max_interval)
+    }
+
+    /// Requeue after the interval requested by an object, with jitter applied.
+    pub fn requeue(&self, requested_seconds: Option<u64>) -> Action {
+        Action::requeue(self.jittered(self.interval(requested_seconds)))
+    }
+
+    /// Like [`ResyncSettings::requeue`], but requeue right after `expires_at` if that comes
+    /// first.
+    pub fn requeue_until(&self, requested_seconds: Option<u64>, expires_at: Option<DateTime<Utc>>) -> Action {
+        let interval = self.jittered(self.interval(requested_seconds));
+        match expires_at.map(|t| (t - Utc::now()).to_std().unwrap_or_default()) {
+            Some(remaining) if remaining < interval => Action::requeue(remaining + Duration::from_secs(1)),
+            _ => Action::requeue(interval),
+        }
+    }
+
+    fn jittered(&self, interval: Duration) -> Duration {
+        if self.jitter <= 0.0 {
+            return interval;
+        }
+        let factor = rand::thread_rng().gen_range(1.0 - self.jitter..=1.0 + self.jitter);
+        interval.mul_f64(factor).max(self.min_interval)
+    }
+}
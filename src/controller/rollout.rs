@@ -0,0 +1,174 @@
+use std::fmt::Debug;
+
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment, StatefulSet};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::NamespaceResourceScope;
+use futures::{future, Stream, StreamExt};
+use kube::{Api, Client, Resource, ResourceExt};
+use kube::api::{Patch, PatchParams};
+use kube::runtime::{reflector, watcher, WatchStreamExt};
+use kube::runtime::events::{Event, EventType};
+use kube::runtime::reflector::{ObjectRef, Store};
+use kube::runtime::reflector::store::Writer;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::controller::{Context, SharedSecretRequest};
+use crate::controller::provenance::CONTENT_HASH_ANNOTATION;
+use crate::controller::scope::scoped_watcher_filtered;
+
+/// Workloads opt in to rollouts by listing SharedSecretRequests or local secrets (comma-separated)
+/// in this annotation.
+const RELOAD_ANNOTATION: &str = "sharedsecretctl.klauser.link/reload";
+/// Prefix of the pod template annotation that holds the content hash of a local secret.
+const CHECKSUM_ANNOTATION_PREFIX: &str = "checksum.sharedsecretctl.klauser.link/";
+
+/// The pod template annotation holding the content hash of `local_secret_name`. The name part of
+/// an annotation key is limited to 63 characters, so it is derived from a hash of the secret name,
+/// which may be longer.
+fn checksum_annotation(local_secret_name: &str) -> String {
+    let digest = Sha256::digest(local_secret_name.as_bytes());
+    let suffix: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}secret-{}", CHECKSUM_ANNOTATION_PREFIX, suffix)
+}
+
+/// Caches of the workloads that opted in to rollouts. Other workloads are not kept, so that
+/// rollouts don't need to list all workloads of a namespace on every sync.
+#[derive(Clone)]
+pub(in crate::controller) struct Workloads {
+    pub deployments: Store<Deployment>,
+    pub stateful_sets: Store<StatefulSet>,
+    pub daemon_sets: Store<DaemonSet>,
+}
+
+/// Whether `workload` opted in to rollouts of any request or local secret.
+fn opted_in<K: Resource>(workload: &K) -> bool {
+    workload.annotations().contains_key(RELOAD_ANNOTATION)
+}
+
+/// Keeps `writer` up to date with the opted-in workloads of kind `K` in the watched namespaces,
+/// and yields the workloads that changed. Watch errors (e.g. missing permissions for workloads)
+/// are retried with a backoff of their own and only logged, so that they neither slow down nor
+/// fail the other triggers of the controller.
+pub(in crate::controller) fn opted_in_workloads<K>(client: &Client, namespaces: &[String], writer: Writer<K>) -> impl Stream<Item = watcher::Result<K>> + Send + 'static
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+{
+    let workloads = scoped_watcher_filtered(client, namespaces, watcher::Config::default().any_semantic(), opted_in)
+        .backoff(watcher::default_backoff());
+    reflector(writer, workloads)
+        .touched_objects()
+        .filter_map(|result| future::ready(match result {
+            Ok(workload) => Some(Ok(workload)),
+            Err(e) => {
+                warn!("Failed to watch {}s for rollouts: {:?}", K::kind(&()), e);
+                None
+            }
+        }))
+}
+
+/// The SharedSecretRequests and local secrets named in the reload annotation of `workload`.
+fn reload_targets<K: Resource>(workload: &K) -> impl Iterator<Item = &str> {
+    workload.annotations().get(RELOAD_ANNOTATION).into_iter()
+        .flat_map(|targets| targets.split(','))
+        .map(str::trim)
+        .filter(|target| !target.is_empty())
+}
+
+/// The SharedSecretRequests whose local secret `workload` is rolled out for, so that a workload
+/// that opts in is rolled out right away.
+pub(in crate::controller) fn requests_to_roll_out<K: Resource>(request_store: &Store<SharedSecretRequest>, workload: &K) -> Vec<ObjectRef<SharedSecretRequest>> {
+    let targets: Vec<&str> = reload_targets(workload).collect();
+    if targets.is_empty() {
+        return vec![];
+    }
+    request_store.state().into_iter()
+        .filter(|request| request.metadata.namespace == workload.meta().namespace)
+        .filter(|request| targets.iter().any(|t| *t == request.name_any() || *t == request.local_secret_name()))
+        .map(|request| ObjectRef::from_obj(&*request))
+        .collect()
+}
+
+impl SharedSecretRequest {
+    /// Rolls out all opted-in Deployments, StatefulSets and DaemonSets in the namespace of the
+    /// request by changing a checksum annotation in their pod template. Failed rollouts are
+    /// reported as events and retried on the next sync; they don't fail the reconciliation.
+    pub(in crate::controller) async fn trigger_rollouts(&self, ctx: &Context, local_secret: &Secret) {
+        let Some(hash) = local_secret.annotations().get(CONTENT_HASH_ANNOTATION) else {
+            return;
+        };
+        self.roll_out(ctx, &ctx.workloads.deployments, &local_secret.name_any(), hash).await;
+        self.roll_out(ctx, &ctx.workloads.stateful_sets, &local_secret.name_any(), hash).await;
+        self.roll_out(ctx, &ctx.workloads.daemon_sets, &local_secret.name_any(), hash).await;
+    }
+
+    async fn roll_out<K>(&self, ctx: &Context, workloads: &Store<K>, local_secret_name: &str, hash: &str)
+    where
+        K: Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + DeserializeOwned + Serialize + Debug,
+    {
+        // we know that SharedSecretRequest is namespaced
+        let ns = &self.meta().namespace.as_ref().unwrap()[..];
+        let name = self.name_any();
+        let api: Api<K> = Api::namespaced(ctx.client.clone(), ns);
+        let checksum_annotation = checksum_annotation(local_secret_name);
+        let events = ctx.event_recorder(self.object_ref(&()));
+
+        for workload in workloads.state() {
+            if workload.meta().namespace.as_deref() != Some(ns)
+                || !reload_targets(&*workload).any(|t| t == name || t == local_secret_name) {
+                continue;
+            }
+            let current = serde_json::to_value(&*workload).unwrap_or_default();
+            let current_hash = current.pointer("/spec/template/metadata/annotations")
+                .and_then(|annotations| annotations.get(&checksum_annotation))
+                .and_then(|hash| hash.as_str());
+            if current_hash == Some(hash) {
+                continue;
+            }
+
+            info!("Rolling out {} \"{}\" in {} because local secret \"{}\" changed", K::kind(&()), workload.name_any(), ns, local_secret_name);
+            let patch = Patch::Merge(json!({
+                "spec": { "template": { "metadata": { "annotations": { &checksum_annotation: hash } } } }
+            }));
+            let event = match api.patch(&workload.name_any(), &PatchParams::apply(&ctx.controller_name), &patch).await {
+                Ok(patched) => Event {
+                    action: "RollingOut".into(),
+                    reason: "RolloutTriggered".into(),
+                    note: Some(format!("Triggered a rollout of {} \"{}\" because local secret \"{}\" changed", K::kind(&()), workload.name_any(), local_secret_name)),
+                    secondary: Some(patched.object_ref(&())),
+                    type_: EventType::Normal,
+                },
+                Err(e) => {
+                    warn!("Failed to roll out {} \"{}\" in {}: {:?}", K::kind(&()), workload.name_any(), ns, e);
+                    Event {
+                        action: "RollingOut".into(),
+                        reason: "RolloutFailed".into(),
+                        note: Some(format!("Failed to roll out {} \"{}\": {}", K::kind(&()), workload.name_any(), e)),
+                        secondary: Some(workload.object_ref(&())),
+                        type_: EventType::Warning,
+                    }
+                }
+            };
+            if let Err(e) = events.publish(event).await {
+                warn!("Failed to publish rollout event for SharedSecretRequest \"{}\" in {}: {:?}", name, ns, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_annotation_is_a_valid_key_for_long_names() {
+        let annotation = checksum_annotation(&"a".repeat(253));
+        let name = annotation.strip_prefix(CHECKSUM_ANNOTATION_PREFIX).unwrap();
+        assert!(name.len() <= 63, "{}", annotation);
+        assert_eq!(annotation, checksum_annotation(&"a".repeat(253)));
+        assert_ne!(annotation, checksum_annotation(&"a".repeat(252)));
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Debug;
+
+use futures::future;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use k8s_openapi::NamespaceResourceScope;
+use kube::{Api, Client, Resource, ResourceExt};
+use kube::runtime::watcher::{self, watcher, Event};
+use kube::runtime::WatchStreamExt;
+use serde::de::DeserializeOwned;
+
+/// The APIs covering the watched namespaces: one per namespace, or a cluster-wide one if
+/// `namespaces` is empty.
+pub(in crate::controller) fn scoped_apis<K>(client: &Client, namespaces: &[String]) -> Vec<Api<K>>
+where
+    K: Resource<Scope = NamespaceResourceScope>,
+    K::DynamicType: Default,
+{
+    if namespaces.is_empty() {
+        vec![Api::all(client.clone())]
+    } else {
+        namespaces.iter().map(|ns| Api::namespaced(client.clone(), ns)).collect()
+    }
+}
+
+/// Watches `K` in the watched namespaces, with one watch per namespace unless all namespaces are
+/// watched, so that no cluster-wide permissions are needed.
+///
+/// The per-namespace watches are merged into a single stream that can feed a reflector. As a
+/// `Restarted` event replaces the whole store, it carries the objects of all namespaces, and it
+/// is held back until every namespace was listed once.
+pub(in crate::controller) fn scoped_watcher<K>(client: &Client, namespaces: &[String], config: watcher::Config) -> BoxStream<'static, watcher::Result<Event<K>>>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+    scoped_watcher_filtered(client, namespaces, config, |_| true)
+}
+
+/// Like [`scoped_watcher`], but only passes on objects matching `filter`. An object that stops
+/// matching is reported as deleted, so that a reflector fed by the stream forgets it.
+pub(in crate::controller) fn scoped_watcher_filtered<K>(client: &Client, namespaces: &[String], config: watcher::Config, filter: fn(&K) -> bool) -> BoxStream<'static, watcher::Result<Event<K>>>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+    if namespaces.is_empty() {
+        return watcher(Api::<K>::all(client.clone()), config)
+            .map_ok(move |event| filter_event(event, filter))
+            .boxed();
+    }
+    let mut listings = Listings::new(namespaces);
+    let watches = listings.namespaces.iter().map(|namespace| {
+        let namespace = namespace.clone();
+        watcher(Api::<K>::namespaced(client.clone(), &namespace), config.clone())
+            .map_ok(move |event| (namespace.clone(), filter_event(event, filter)))
+            .boxed()
+    }).collect::<Vec<_>>();
+    stream::select_all(watches)
+        .try_filter_map(move |(namespace, event)| future::ready(Ok(listings.apply(namespace, event))))
+        .boxed()
+}
+
+fn filter_event<K>(event: Event<K>, filter: fn(&K) -> bool) -> Event<K> {
+    match event {
+        Event::Applied(obj) if !filter(&obj) => Event::Deleted(obj),
+        Event::Restarted(objs) => Event::Restarted(objs.into_iter().filter(filter).collect()),
+        event => event,
+    }
+}
+
+/// The objects touched in the watched namespaces, for triggering reconciliations. Unlike
+/// [`scoped_watcher`], this keeps no copy of the objects, as triggers don't need a merged
+/// `Restarted` event.
+pub(in crate::controller) fn scoped_trigger<K>(client: &Client, namespaces: &[String], config: watcher::Config) -> BoxStream<'static, watcher::Result<K>>
+where
+    K: Resource<Scope = NamespaceResourceScope, DynamicType = ()> + Clone + DeserializeOwned + Debug + Send + 'static,
+{
+    let watches = scoped_apis::<K>(client, namespaces).into_iter()
+        .map(|api| watcher(api, config.clone()).touched_objects().boxed())
+        .collect::<Vec<_>>();
+    stream::select_all(watches).boxed()
+}
+
+/// The last known objects of every namespace that was listed so far.
+struct Listings<K> {
+    namespaces: BTreeSet<String>,
+    objects: HashMap<String, BTreeMap<String, K>>,
+}
+
+impl<K: Resource + Clone> Listings<K> {
+    /// Duplicate namespaces are only watched once.
+    fn new(namespaces: &[String]) -> Self {
+        Listings { namespaces: namespaces.iter().cloned().collect(), objects: HashMap::new() }
+    }
+
+    /// Records an event of the watch of `namespace` and turns it into an event of the merged
+    /// stream, if any.
+    fn apply(&mut self, namespace: String, event: Event<K>) -> Option<Event<K>> {
+        match event {
+            Event::Applied(obj) => {
+                if let Some(objects) = self.objects.get_mut(&namespace) {
+                    objects.insert(obj.name_any(), obj.clone());
+                }
+                Some(Event::Applied(obj))
+            }
+            Event::Deleted(obj) => {
+                if let Some(objects) = self.objects.get_mut(&namespace) {
+                    objects.remove(&obj.name_any());
+                }
+                Some(Event::Deleted(obj))
+            }
+            Event::Restarted(objs) => {
+                self.objects.insert(namespace, objs.into_iter().map(|obj| (obj.name_any(), obj)).collect());
+                if self.namespaces.iter().any(|namespace| !self.objects.contains_key(namespace)) {
+                    return None;
+                }
+                Some(Event::Restarted(self.objects.values().flat_map(|objects| objects.values().cloned()).collect()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k8s_openapi::api::core::v1::Secret;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+
+    use super::*;
+
+    fn secret(namespace: &str, name: &str) -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                namespace: Some(namespace.to_string()),
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn namespaces(namespaces: &[&str]) -> Vec<String> {
+        namespaces.iter().map(|ns| ns.to_string()).collect()
+    }
+
+    /// (namespace, name) of the objects of a `Restarted` event, sorted.
+    fn restarted(event: Option<Event<Secret>>) -> Vec<(String, String)> {
+        match event {
+            Some(Event::Restarted(objs)) => {
+                let mut keys: Vec<_> = objs.iter().map(|obj| (obj.namespace().unwrap(), obj.name_any())).collect();
+                keys.sort();
+                keys
+            }
+            event => panic!("expected Restarted, got {:?}", event),
+        }
+    }
+
+    fn keys(keys: &[(&str, &str)]) -> Vec<(String, String)> {
+        keys.iter().map(|(ns, name)| (ns.to_string(), name.to_string())).collect()
+    }
+
+    #[test]
+    fn restarted_is_held_back_until_every_namespace_was_listed() {
+        let mut listings = Listings::new(&namespaces(&["a", "b"]));
+        assert!(listings.apply("a".to_string(), Event::Restarted(vec![secret("a", "x")])).is_none());
+        let event = listings.apply("b".to_string(), Event::Restarted(vec![secret("b", "y")]));
+        assert_eq!(restarted(event), keys(&[("a", "x"), ("b", "y")]));
+    }
+
+    #[test]
+    fn duplicate_namespaces_are_listed_once() {
+        let mut listings = Listings::new(&namespaces(&["a", "a"]));
+        let event = listings.apply("a".to_string(), Event::Restarted(vec![secret("a", "x")]));
+        assert_eq!(restarted(event), keys(&[("a", "x")]));
+    }
+
+    #[test]
+    fn restart_of_one_namespace_keeps_the_others() {
+        let mut listings = Listings::new(&namespaces(&["a", "b"]));
+        listings.apply("a".to_string(), Event::Restarted(vec![secret("a", "x")]));
+        listings.apply("b".to_string(), Event::Restarted(vec![secret("b", "y")]));
+
+        let event = listings.apply("a".to_string(), Event::Restarted(vec![secret("a", "z")]));
+        assert_eq!(restarted(event), keys(&[("a", "z"), ("b", "y")]));
+    }
+
+    #[test]
+    fn applied_and_deleted_objects_are_part_of_the_next_restart() {
+        let mut listings = Listings::new(&namespaces(&["a", "b"]));
+        listings.apply("a".to_string(), Event::Restarted(vec![secret("a", "x")]));
+        listings.apply("b".to_string(), Event::Restarted(vec![secret("b", "y")]));
+
+        assert!(matches!(listings.apply("a".to_string(), Event::Applied(secret("a", "w"))), Some(Event::Applied(_))));
+        assert!(matches!(listings.apply("b".to_string(), Event::Deleted(secret("b", "y"))), Some(Event::Deleted(_))));
+
+        let event = listings.apply("b".to_string(), Event::Restarted(vec![]));
+        assert_eq!(restarted(event), keys(&[("a", "w"), ("a", "x")]));
+    }
+
+    #[test]
+    fn objects_that_stop_matching_the_filter_are_deleted() {
+        let named_x = |secret: &Secret| secret.name_any() == "x";
+        assert!(matches!(filter_event(Event::Applied(secret("a", "x")), named_x), Event::Applied(_)));
+        assert!(matches!(filter_event(Event::Applied(secret("a", "y")), named_x), Event::Deleted(_)));
+        assert!(matches!(filter_event(Event::Deleted(secret("a", "y")), named_x), Event::Deleted(_)));
+        assert_eq!(restarted(Some(filter_event(Event::Restarted(vec![secret("a", "x"), secret("a", "y")]), named_x))), keys(&[("a", "x")]));
+    }
+}
@@ -1,35 +1,53 @@
 use std::sync::Arc;
-use std::time::Duration;
 use k8s_openapi::api::core::v1::Secret;
 use kube::{Api, Resource, ResourceExt};
 use kube::api::{Patch, PatchParams};
 use kube::runtime::controller::Action;
+use kube::runtime::reflector::ObjectRef;
 use kube::runtime::finalizer;
-use kube::runtime::finalizer::Event;
+use kube::runtime::events::{Event, EventType};
+use kube::runtime::finalizer::Event as Finalizer;
 use serde_json::json;
 use tracing::{debug, info, warn};
-use crate::controller::{Context, CONTROLLER_NAME, SharedSecret, SharedSecretState, SharedSecretStatus};
+use crate::controller::conditions::{conditions_differ, merge_conditions};
+use crate::controller::{Context, reconcile_requested_at, record_reconcile_result, SharedSecret, SharedSecretRequestReference, SharedSecretState, SharedSecretStatus};
 use crate::{Error, Result};
 
 static SHARED_SECRET_FINALIZER: &str = "sharedsecretctl.klauser.link/shared-secret";
+static FORCE_DELETE_ANNOTATION: &str = "sharedsecretctl.klauser.link/force-delete";
 
 pub(in crate::controller) async fn reconcile_shared_secret(shared_secret: Arc<SharedSecret>, ctx: Arc<Context>) -> Result<Action> {
     let ns = shared_secret.namespace().unwrap(); // we know that SharedSecret is namespaced
+    if !ctx.leadership.is_leader() {
+        // standbys keep their caches warm, but leave all writes to the leader
+        return Ok(Action::await_change());
+    }
+    if !ctx.watches_namespace(&ns) {
+        return Ok(Action::await_change());
+    }
+    let _permit = ctx.reconcile_permit().await;
     let shared_secrets = Api::<SharedSecret>::namespaced(ctx.client.clone(), &ns);
 
     info!("Reconciling SharedSecret \"{}\" in {}", shared_secret.name_any(), ns);
-    finalizer(
+    let _timer = ctx.metrics.reconcile_started("shared_secret");
+    let _progress = ctx.health.reconcile_started();
+    let obj_ref = ObjectRef::from_obj(&*shared_secret);
+    let previous_error = shared_secret.status.as_ref().and_then(|s| s.last_error.clone());
+    let result = finalizer(
         &shared_secrets,
         SHARED_SECRET_FINALIZER,
         shared_secret,
         |event| async {
             match event {
-                Event::Apply(shared_secret) => shared_secret.apply(ctx.clone()).await,
-                Event::Cleanup(shared_secret) => shared_secret.cleanup(ctx.clone()).await,
+                Finalizer::Apply(shared_secret) => shared_secret.apply(ctx.clone()).await,
+                Finalizer::Cleanup(shared_secret) => shared_secret.cleanup(ctx.clone()).await,
             }
         }
     ).await
-        .map_err(|e| Error::FinalizerError(Box::new(e)))
+        .map_err(|e| Error::FinalizerError(Box::new(e)));
+
+    record_reconcile_result(&ctx, &shared_secrets, &ctx.shared_secret_backoff, "shared_secret", &obj_ref, previous_error, &result).await;
+    result
 }
 
 impl SharedSecret {
@@ -38,39 +56,105 @@ impl SharedSecret {
         let client = ctx.client.clone();
         let secrets: Api<Secret> = Api::namespaced(client.clone(), ns);
 
+        if self.spec.suspend {
+            debug!("SharedSecret \"{}.{}\" is suspended", self.name_any(), ns);
+            self.update_status(&ctx, SharedSecretState::Suspended).await?;
+
+            return Ok(ctx.resync.requeue(self.spec.resync_interval_seconds));
+        }
+
         let Some(secret) = secrets.get_opt(&self.spec.secret_name).await? else {
             debug!("Secret \"{}.{}\" is missing", self.spec.secret_name, ns);
-            self.update_status(&ctx, SharedSecretStatus {
-                state: SharedSecretState::SecretMissing,
-            }).await?;
+            self.update_status(&ctx, SharedSecretState::SecretMissing).await?;
 
-            return Ok(Action::requeue(Duration::from_secs(5 * 60)));
+            return Ok(ctx.resync.requeue(self.spec.resync_interval_seconds));
         };
 
-        if secret.data.map(|d| d.is_empty()).unwrap_or(true) {
-            debug!("Secret \"{}.{}\" is missing", self.spec.secret_name, ns);
-            self.update_status(&ctx, SharedSecretStatus {
-                state: SharedSecretState::SecretInvalid,
-            }).await?;
+        let exported = self.spec.export.apply(&secret.data.unwrap_or_default());
+        if exported.is_empty() {
+            debug!("Secret \"{}.{}\" has no exported data", self.spec.secret_name, ns);
+            self.update_status(&ctx, SharedSecretState::SecretInvalid).await?;
 
-            return Ok(Action::requeue(Duration::from_secs(5 * 60)));
+            return Ok(ctx.resync.requeue(self.spec.resync_interval_seconds));
         }
 
-        self.update_status(&ctx, SharedSecretStatus {
-            state: SharedSecretState::Valid,
-        }).await?;
+        let mut new_status = self.next_status(SharedSecretState::Valid);
+        new_status.exported_keys = exported.into_keys().collect();
+        self.write_status(&ctx, new_status).await?;
 
-        // If no events were received, check back every 5 minutes
-        Ok(Action::requeue(Duration::from_secs(5 * 60)))
+        // If no events were received, check back after the resync interval
+        Ok(ctx.resync.requeue(self.spec.resync_interval_seconds))
     }
 
-    async fn cleanup(&self, _ctx: Arc<Context>) -> Result<Action> {
+    async fn cleanup(&self, ctx: Arc<Context>) -> Result<Action> {
+        let ns = &self.meta().namespace.as_ref().unwrap()[..]; // we know that SharedSecret is namespaced
+        let name = &self.metadata.name.as_ref().expect("SharedSecret to have a name")[..];
 
-        // If no events were received, check back every 5 minutes
-        Ok(Action::requeue(Duration::from_secs(5 * 60)))
+        let mut dependents: Vec<SharedSecretRequestReference> = ctx.request_index.find(ns, name).into_iter()
+            .map(|request| SharedSecretRequestReference {
+                namespace: request.namespace.unwrap_or_default(),
+                name: request.name,
+            })
+            .collect();
+        dependents.sort_by(|a, b| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)));
+        if dependents.is_empty() {
+            return Ok(Action::await_change());
+        }
+
+        let dependent_names = dependents.iter()
+            .map(|d| format!("{}.{}", d.name, d.namespace))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let events = ctx.event_recorder(self.object_ref(&()));
+        if self.annotations().get(FORCE_DELETE_ANNOTATION).map(|v| &v[..]) == Some("true") {
+            warn!("Force-deleting SharedSecret \"{}.{}\" that is still referenced by {}", name, ns, dependent_names);
+            events.publish(Event {
+                action: "Deleting".into(),
+                reason: "ForcedDeletion".into(),
+                note: Some(format!("Deleted despite SharedSecretRequests still referencing it: {}", dependent_names)),
+                secondary: None,
+                type_: EventType::Warning,
+            }).await?;
+            return Ok(Action::await_change());
+        }
+
+        let status = self.status.clone().unwrap_or_default();
+        if status.state != SharedSecretState::DeletionBlocked || status.dependents != dependents {
+            events.publish(Event {
+                action: "Deleting".into(),
+                reason: "DeletionBlocked".into(),
+                note: Some(format!("SharedSecretRequests still reference this SharedSecret: {}. Annotate with {}=true to delete anyway.", dependent_names, FORCE_DELETE_ANNOTATION)),
+                secondary: None,
+                type_: EventType::Warning,
+            }).await?;
+        }
+        let mut new_status = status.with_state(SharedSecretState::DeletionBlocked, self.metadata.generation);
+        new_status.dependents = dependents;
+        self.write_status(&ctx, new_status).await?;
+
+        // keep the finalizer; the SharedSecret is reconciled again once one of its dependents goes away
+        Err(Error::DeletionBlocked(dependent_names))
+    }
+
+    async fn update_status(&self, ctx: &Context, state: SharedSecretState) -> Result<()> {
+        self.write_status(ctx, self.next_status(state)).await
     }
 
-    async fn update_status(&self, ctx: &Context, new_status: SharedSecretStatus) -> Result<()> {
+    /// The status after transitioning into `state`. Unless suspended, this acknowledges a
+    /// requested reconciliation.
+    fn next_status(&self, state: SharedSecretState) -> SharedSecretStatus {
+        let previous = self.status.clone().unwrap_or_default();
+        let exported_keys = previous.exported_keys.clone();
+        let mut new_status = previous.with_state(state, self.metadata.generation);
+        if state == SharedSecretState::Suspended {
+            new_status.exported_keys = exported_keys;
+        } else if let Some(requested_at) = reconcile_requested_at(self) {
+            new_status.last_handled_reconcile_at = Some(requested_at.to_string());
+        }
+        new_status
+    }
+
+    async fn write_status(&self, ctx: &Context, new_status: SharedSecretStatus) -> Result<()> {
         if self.status.as_ref().map(|s| s.update_required(&new_status)) == Some(false) {
             debug!(new_status=?&new_status, "Not updating status of SharedSecret because it is unchanged.");
             return Ok(());
@@ -86,24 +170,43 @@ impl SharedSecret {
             "kind": "SharedSecret",
             "status": new_status
         }));
-        let ps = PatchParams::apply(CONTROLLER_NAME).force();
+        let ps = PatchParams::apply(&ctx.controller_name).force();
         shared_secret_requests.patch_status(name, &ps, &new_status_patch)
             .await
-            .map_err(Error::KubeError)?;
+            .map_err(Error::from)?;
 
         Ok(())
     }
 }
 
 impl SharedSecretStatus {
+    fn with_state(self, state: SharedSecretState, generation: Option<i64>) -> Self {
+        SharedSecretStatus {
+            state,
+            observed_generation: generation,
+            conditions: merge_conditions(&self.conditions, state.desired_conditions(), generation),
+            last_handled_reconcile_at: self.last_handled_reconcile_at,
+            last_error: self.last_error,
+            exported_keys: vec![],
+            dependents: vec![],
+        }
+    }
+
     pub fn update_required(&self, other: &Self) -> bool {
         self.state != other.state
+            || self.observed_generation != other.observed_generation
+            || conditions_differ(&self.conditions, &other.conditions)
+            || self.last_handled_reconcile_at != other.last_handled_reconcile_at
+            || self.last_error != other.last_error
+            || self.exported_keys != other.exported_keys
+            || self.dependents != other.dependents
     }
 }
 
 
 
-pub(in crate::controller) fn shared_secret_error_policy(_doc: Arc<SharedSecret>, error: &Error, _ctx: Arc<Context>) -> Action {
-    warn!("SharedSecret reconcile failed: {:?}", error);
-    Action::requeue(Duration::from_secs(5 * 60))
+pub(in crate::controller) fn shared_secret_error_policy(shared_secret: Arc<SharedSecret>, error: &Error, ctx: Arc<Context>) -> Action {
+    let delay = ctx.shared_secret_backoff.next_delay(&shared_secret, error);
+    warn!("SharedSecret reconcile failed, retrying in {:?}: {:?}", delay, error);
+    Action::requeue(delay)
 }
@@ -1,5 +1,7 @@
+use kube::runtime::finalizer;
 use thiserror::Error;
 
+pub mod config;
 pub mod controller;
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -9,11 +11,151 @@ pub enum Error {
     #[error("SerializationError: {0}")]
     SerializationError(#[source] serde_json::Error),
 
+    /// Any error of the Kubernetes API that doesn't fit one of the more specific variants.
     #[error("Kube Error: {0}")]
-    KubeError(#[source] #[from] kube::Error),
+    KubeError(#[source] kube::Error),
+
+    #[error("Not found: {0}")]
+    NotFound(#[source] kube::Error),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(#[source] kube::Error),
+
+    #[error("Conflict: {0}")]
+    Conflict(#[source] kube::Error),
+
+    /// Throttling, server errors and connection problems, which usually go away on their own.
+    #[error("Transient error: {0}")]
+    Transient(#[source] kube::Error),
+
+    /// The Kubernetes API rejected an object that was derived from the spec.
+    #[error("Invalid spec: {0}")]
+    InvalidSpec(String),
 
     #[error("Finalizer Error: {0}")]
     // NB: awkward type because finalizer::Error embeds the reconciler error (which is this)
     // so boxing this error to break cycles
-    FinalizerError(#[source] Box<kube::runtime::finalizer::Error<Error>>),
-}
\ No newline at end of file
+    FinalizerError(#[source] Box<finalizer::Error<Error>>),
+
+    #[error("Deletion blocked by dependents: {0}")]
+    DeletionBlocked(String),
+}
+
+impl From<kube::Error> for Error {
+    fn from(error: kube::Error) -> Self {
+        match &error {
+            kube::Error::Api(response) => match response.code {
+                404 => Error::NotFound(error),
+                403 => Error::Forbidden(error),
+                409 => Error::Conflict(error),
+                400 | 422 => Error::InvalidSpec(response.message.clone()),
+                408 | 410 | 429 | 500.. => Error::Transient(error),
+                _ => Error::KubeError(error),
+            },
+            kube::Error::HyperError(_) | kube::Error::Service(_) | kube::Error::ReadEvents(_) => Error::Transient(error),
+            _ => Error::KubeError(error),
+        }
+    }
+}
+
+impl Error {
+    /// Name of the variant, for use as a metric label.
+    pub fn metric_label(&self) -> &'static str {
+        match self {
+            Error::SerializationError(_) => "SerializationError",
+            Error::KubeError(_) => "KubeError",
+            Error::NotFound(_) => "NotFound",
+            Error::Forbidden(_) => "Forbidden",
+            Error::Conflict(_) => "Conflict",
+            Error::Transient(_) => "Transient",
+            Error::InvalidSpec(_) => "InvalidSpec",
+            Error::FinalizerError(e) => match e.as_ref() {
+                finalizer::Error::ApplyFailed(e) | finalizer::Error::CleanupFailed(e) => e.metric_label(),
+                _ => "FinalizerError",
+            },
+            Error::DeletionBlocked(_) => "DeletionBlocked",
+        }
+    }
+
+    /// Whether retrying is pointless until the involved objects (or the permissions of the
+    /// controller) change.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Error::SerializationError(_) | Error::Forbidden(_) | Error::InvalidSpec(_) => true,
+            Error::FinalizerError(e) => match e.as_ref() {
+                finalizer::Error::ApplyFailed(e) | finalizer::Error::CleanupFailed(e) => e.is_permanent(),
+                finalizer::Error::AddFinalizer(e) | finalizer::Error::RemoveFinalizer(e) => matches!(e, kube::Error::Api(response) if response.code == 403),
+                finalizer::Error::UnnamedObject => true,
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kube::error::ErrorResponse;
+
+    use super::*;
+
+    fn api_error(code: u16) -> kube::Error {
+        kube::Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: format!("failed with {}", code),
+            reason: "Test".to_string(),
+            code,
+        })
+    }
+
+    fn io_error() -> kube::Error {
+        kube::Error::ReadEvents(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset"))
+    }
+
+    #[test]
+    fn kube_errors_are_mapped_by_status_code() {
+        assert!(matches!(Error::from(api_error(404)), Error::NotFound(_)));
+        assert!(matches!(Error::from(api_error(403)), Error::Forbidden(_)));
+        assert!(matches!(Error::from(api_error(409)), Error::Conflict(_)));
+        assert!(matches!(Error::from(api_error(400)), Error::InvalidSpec(message) if message == "failed with 400"));
+        assert!(matches!(Error::from(api_error(422)), Error::InvalidSpec(message) if message == "failed with 422"));
+        for code in [408, 410, 429, 500, 503] {
+            assert!(matches!(Error::from(api_error(code)), Error::Transient(_)), "{} should be transient", code);
+        }
+        assert!(matches!(Error::from(api_error(401)), Error::KubeError(_)));
+    }
+
+    #[test]
+    fn connection_errors_are_transient() {
+        assert!(matches!(Error::from(io_error()), Error::Transient(_)));
+        assert!(matches!(Error::from(kube::Error::LinesCodecMaxLineLengthExceeded), Error::KubeError(_)));
+    }
+
+    #[test]
+    fn permanent_errors() {
+        assert!(Error::InvalidSpec("invalid".to_string()).is_permanent());
+        assert!(Error::from(api_error(403)).is_permanent());
+        assert!(Error::SerializationError(serde_json::from_str::<u8>("x").unwrap_err()).is_permanent());
+
+        assert!(!Error::from(api_error(404)).is_permanent());
+        assert!(!Error::from(api_error(409)).is_permanent());
+        assert!(!Error::from(api_error(500)).is_permanent());
+        assert!(!Error::from(io_error()).is_permanent());
+        assert!(!Error::DeletionBlocked("dependents".to_string()).is_permanent());
+    }
+
+    #[test]
+    fn finalizer_errors_are_permanent_depending_on_their_cause() {
+        let finalizer_error = |e| Error::FinalizerError(Box::new(e));
+
+        assert!(finalizer_error(finalizer::Error::ApplyFailed(Error::InvalidSpec("invalid".to_string()))).is_permanent());
+        assert!(!finalizer_error(finalizer::Error::ApplyFailed(Error::from(api_error(500)))).is_permanent());
+        assert!(finalizer_error(finalizer::Error::CleanupFailed(Error::from(api_error(403)))).is_permanent());
+        assert!(!finalizer_error(finalizer::Error::CleanupFailed(Error::DeletionBlocked("dependents".to_string()))).is_permanent());
+
+        assert!(finalizer_error(finalizer::Error::AddFinalizer(api_error(403))).is_permanent());
+        assert!(!finalizer_error(finalizer::Error::AddFinalizer(api_error(409))).is_permanent());
+        assert!(finalizer_error(finalizer::Error::RemoveFinalizer(api_error(403))).is_permanent());
+        assert!(!finalizer_error(finalizer::Error::RemoveFinalizer(io_error())).is_permanent());
+        assert!(finalizer_error(finalizer::Error::UnnamedObject).is_permanent());
+    }
+}
@@ -1,17 +1,77 @@
 use std::io;
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use axum::extract;
+use axum::http::StatusCode;
+use axum::Router;
+use axum::routing::get;
+use controller::config::{Config, LogFormat};
+use controller::controller::State;
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::util::SubscriberInitExt;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let config = Config::load()?;
+
     let collector = tracing_subscriber::fmt()
         .with_writer(io::stderr)
-        .json()
-        .with_env_filter(EnvFilter::from_default_env())
-        .finish();
-    collector.init();
+        .with_env_filter(EnvFilter::from_default_env());
+    match config.log_format {
+        LogFormat::Json => collector.json().finish().init(),
+        LogFormat::Pretty => collector.pretty().finish().init(),
+    }
+
+    let state = State::new(&config);
+    let metrics_app = Router::new()
+        .route("/metrics", get(metrics));
+    let probe_app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+    let servers = async {
+        if config.metrics_address == config.probe_address {
+            serve(config.metrics_address, metrics_app.merge(probe_app), state.clone()).await
+        } else {
+            tokio::try_join!(
+                serve(config.metrics_address, metrics_app, state.clone()),
+                serve(config.probe_address, probe_app, state.clone()),
+            ).map(|_| ())
+        }
+    };
 
-    controller::controller::run(controller::controller::State {}).await;
+    tokio::select! {
+        _ = controller::controller::run(state.clone()) => {},
+        result = servers => result?,
+    }
 
     Ok(())
 }
+
+async fn serve(address: SocketAddr, app: Router<State>, state: State) -> anyhow::Result<()> {
+    axum::Server::try_bind(&address)
+        .with_context(|| format!("Failed to listen on {}", address))?
+        .serve(app.with_state(state).into_make_service())
+        .await
+        .with_context(|| format!("Failed to serve on {}", address))
+}
+
+async fn metrics(extract::State(state): extract::State<State>) -> String {
+    state.metrics()
+}
+
+async fn healthz(extract::State(state): extract::State<State>) -> (StatusCode, &'static str) {
+    if state.is_alive() {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "reconcile loop is not making progress")
+    }
+}
+
+async fn readyz(extract::State(state): extract::State<State>) -> (StatusCode, &'static str) {
+    if state.is_ready() {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "caches are not synced yet")
+    }
+}